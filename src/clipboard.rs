@@ -0,0 +1,190 @@
+use crate::Terminal;
+use clipboard::ClipboardContext;
+use clipboard::ClipboardProvider;
+use std::env;
+use std::io::{Error, Read, Write};
+use std::process::{Command, Stdio};
+
+/// An external program that accesses the clipboard, which is only tried if the environment
+/// variable telling that its display server is running is set.
+struct Helper {
+    program: &'static str,
+    args: &'static [&'static str],
+    env_var: &'static str,
+}
+
+const COPY_HELPERS: [Helper; 4] = [
+    Helper {
+        program: "wl-copy",
+        args: &[],
+        env_var: "WAYLAND_DISPLAY",
+    },
+    Helper {
+        program: "xclip",
+        args: &["-selection", "clipboard"],
+        env_var: "DISPLAY",
+    },
+    Helper {
+        program: "xsel",
+        args: &["--clipboard", "--input"],
+        env_var: "DISPLAY",
+    },
+    Helper {
+        program: "tmux",
+        args: &["load-buffer", "-"],
+        env_var: "TMUX",
+    },
+];
+
+const PASTE_HELPERS: [Helper; 4] = [
+    Helper {
+        program: "wl-paste",
+        args: &["--no-newline"],
+        env_var: "WAYLAND_DISPLAY",
+    },
+    Helper {
+        program: "xclip",
+        args: &["-selection", "clipboard", "-out"],
+        env_var: "DISPLAY",
+    },
+    Helper {
+        program: "xsel",
+        args: &["--clipboard", "--output"],
+        env_var: "DISPLAY",
+    },
+    Helper {
+        program: "tmux",
+        args: &["save-buffer", "-"],
+        env_var: "TMUX",
+    },
+];
+
+impl Helper {
+    fn is_available(&self) -> bool {
+        env::var_os(self.env_var).is_some()
+    }
+
+    fn command(&self) -> Command {
+        let mut command = Command::new(self.program);
+        command.args(self.args).stderr(Stdio::null());
+        command
+    }
+
+    fn copy(&self, contents: &str) -> Result<(), Error> {
+        let mut child = self
+            .command()
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .spawn()?;
+        // The stdin is closed before waiting, so that the helper sees the end of the contents,
+        // and the helper is waited for even if writing failed, so that it doesn't linger.
+        let written = child
+            .stdin
+            .take()
+            .map_or(Ok(()), |mut stdin| stdin.write_all(contents.as_bytes()));
+        let status = child.wait()?;
+        written?;
+        if status.success() {
+            Ok(())
+        } else {
+            Err(Error::other(self.program))
+        }
+    }
+
+    fn paste(&self) -> Result<String, Error> {
+        let mut child = self
+            .command()
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .spawn()?;
+        let mut contents = String::new();
+        if let Some(mut stdout) = child.stdout.take() {
+            stdout.read_to_string(&mut contents)?;
+        }
+        if child.wait()?.success() {
+            Ok(contents)
+        } else {
+            Err(Error::other(self.program))
+        }
+    }
+}
+
+/// Where the contents of the clipboard went to or came from.
+#[derive(PartialEq, Copy, Clone)]
+pub enum Backend {
+    X11,
+    Helper(&'static str),
+    /// The terminal itself, by an OSC 52 escape sequence. Terminals don't answer it, so whether
+    /// the contents arrived is unknown: those that don't support it ignore it.
+    Osc52,
+    Register,
+}
+
+impl Backend {
+    #[must_use]
+    pub fn name(&self) -> &'static str {
+        match self {
+            Backend::X11 => "X11",
+            Backend::Helper(program) => program,
+            Backend::Osc52 => "terminal (unconfirmed)",
+            Backend::Register => "internal register",
+        }
+    }
+}
+
+/// The system clipboard, falling back to whatever is available when there is no X11 display,
+/// e.g. over SSH, in containers or on Wayland.
+pub struct Clipboard {
+    /// Kept alive for as long as the editor runs, since the copied contents are served from it.
+    x11: Option<ClipboardContext>,
+    /// The contents of the last copy. Pasted when no other backend can be read from, which
+    /// includes the terminal.
+    register: String,
+}
+
+impl Default for Clipboard {
+    fn default() -> Self {
+        Self {
+            x11: ClipboardContext::new().ok(),
+            register: String::new(),
+        }
+    }
+}
+
+impl Clipboard {
+    /// Copies to the first backend that accepts the contents, and returns which one it was. The
+    /// terminal is the last resort, and only fails to accept them if it can't be written to, in
+    /// which case the contents are only in the register.
+    pub fn set_contents(&mut self, contents: &str) -> Backend {
+        contents.clone_into(&mut self.register);
+        if let Some(x11) = &mut self.x11 {
+            if x11.set_contents(contents.to_owned()).is_ok() {
+                return Backend::X11;
+            }
+        }
+        for helper in COPY_HELPERS.iter().filter(|helper| helper.is_available()) {
+            if helper.copy(contents).is_ok() {
+                return Backend::Helper(helper.program);
+            }
+        }
+        if Terminal::set_clipboard(contents).is_ok() {
+            return Backend::Osc52;
+        }
+        Backend::Register
+    }
+
+    /// Pastes from the first backend that can be read from, and returns which one it was.
+    pub fn get_contents(&mut self) -> (String, Backend) {
+        if let Some(x11) = &mut self.x11 {
+            if let Ok(contents) = x11.get_contents() {
+                return (contents, Backend::X11);
+            }
+        }
+        for helper in PASTE_HELPERS.iter().filter(|helper| helper.is_available()) {
+            if let Ok(contents) = helper.paste() {
+                return (contents, Backend::Helper(helper.program));
+            }
+        }
+        (self.register.clone(), Backend::Register)
+    }
+}
@@ -0,0 +1,66 @@
+use std::env;
+use std::fs;
+
+/// Replaces a leading `~` by the home directory.
+#[must_use]
+pub fn expand_home(path: &str) -> String {
+    match path.strip_prefix('~') {
+        Some(rest) if rest.is_empty() || rest.starts_with('/') => env::var_os("HOME").map_or_else(
+            || path.to_owned(),
+            |home| format!("{}{rest}", home.to_string_lossy()),
+        ),
+        _ => path.to_owned(),
+    }
+}
+
+/// The paths that `input` can be completed to, sorted, with a `/` after directories. The
+/// directory part is kept as it was typed, `~` included.
+#[must_use]
+pub fn candidates(input: &str) -> Vec<String> {
+    if input == "~" {
+        return vec!["~/".to_owned()];
+    }
+    let (directory, prefix) = match input.rsplit_once('/') {
+        Some((directory, prefix)) => (format!("{directory}/"), prefix),
+        None => (String::new(), input),
+    };
+    let searched = if directory.is_empty() {
+        ".".to_owned()
+    } else {
+        expand_home(&directory)
+    };
+    let Ok(entries) = fs::read_dir(searched) else {
+        return Vec::new();
+    };
+    let mut candidates: Vec<String> = entries
+        .flatten()
+        .filter_map(|entry| {
+            let name = entry.file_name().into_string().ok()?;
+            // Hidden files are only offered when their name is started on.
+            if !name.starts_with(prefix) || (name.starts_with('.') && !prefix.starts_with('.')) {
+                return None;
+            }
+            let slash = if entry.path().is_dir() { "/" } else { "" };
+            Some(format!("{directory}{name}{slash}"))
+        })
+        .collect();
+    candidates.sort();
+    candidates
+}
+
+/// The longest start that all of `candidates` share.
+#[must_use]
+pub fn common_prefix(candidates: &[String]) -> String {
+    let Some(first) = candidates.first() else {
+        return String::new();
+    };
+    let mut prefix = first.as_str();
+    for candidate in candidates {
+        while !candidate.starts_with(prefix) {
+            let mut chars = prefix.chars();
+            chars.next_back();
+            prefix = chars.as_str();
+        }
+    }
+    prefix.to_owned()
+}
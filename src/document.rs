@@ -1,36 +1,320 @@
+use crate::encoding::FileEncoding;
+use crate::history::Edit;
+use crate::large_file::LargeFile;
 use crate::row;
+use crate::search::Query;
 use crate::FileType;
+use crate::History;
+use crate::LineEnding;
 use crate::Position;
 use crate::Row;
-use std::fs;
+use crate::RowTree;
+use core::cmp;
+use core::hash::{Hash, Hasher};
+use core::mem;
+use core::ops::Range;
+use std::env;
+use std::fs::{self, Permissions};
+use std::hash::DefaultHasher;
 use std::io::{Error, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::time::SystemTime;
+use unicode_segmentation::UnicodeSegmentation;
+
+/// The size from which files are opened as large files, unless `HECTO_LARGE_FILE_SIZE` says
+/// otherwise.
+const DEFAULT_LARGE_FILE_SIZE: u64 = 64 << 20;
+
+/// The pairs of brackets that indentation follows.
+const BRACKETS: [(char, char); 3] = [('(', ')'), ('[', ']'), ('{', '}')];
+
+/// What the file was like when it was last read or written, to tell whether another program
+/// changed it since.
+#[derive(Clone, Copy)]
+struct DiskState {
+    modified: Option<SystemTime>,
+    len: u64,
+    hash: u64,
+}
+
+impl DiskState {
+    /// The state of a large file, which isn't read to be hashed.
+    fn of_large(path: &Path) -> Option<Self> {
+        let metadata = fs::metadata(path).ok()?;
+        Some(Self {
+            modified: metadata.modified().ok(),
+            len: metadata.len(),
+            hash: 0,
+        })
+    }
+
+    fn of(path: &Path, bytes: &[u8]) -> Option<Self> {
+        let metadata = fs::metadata(path).ok()?;
+        let mut hasher = DefaultHasher::new();
+        bytes.hash(&mut hasher);
+        Some(Self {
+            modified: metadata.modified().ok(),
+            len: metadata.len(),
+            hash: hasher.finish(),
+        })
+    }
+}
+
+/// How the rows of the file were broken, which is kept when it's written back.
+#[derive(Default)]
+struct RowBreaks {
+    /// The line ending of new row breaks; the one most rows had when the file was read.
+    ending: LineEnding,
+    /// Whether the file had rows with either line ending.
+    is_mixed: bool,
+    /// Whether the file didn't end with a row break, which is kept that way on save.
+    lacks_final_newline: bool,
+}
 
 #[derive(Default)]
 pub struct Document {
-    rows: Vec<Row>,
+    rows: RowTree,
     pub filename: Option<String>,
     /// Whether the document has been modified since the last save.
     is_dirty: bool,
     file_type: FileType,
+    history: History,
+    /// The edits made since they were last taken, including those of undo and redo, so that
+    /// positions kept elsewhere can follow them.
+    recent_edits: Vec<Edit>,
+    row_breaks: RowBreaks,
+    /// The encoding the file was read in, which it is written back in.
+    encoding: FileEncoding,
+    /// `None` if the file has never been read or written.
+    disk_state: Option<DiskState>,
+    /// Whether edits are rejected, so that the document is only looked at.
+    read_only: bool,
+    /// The file the rows are read from as they are shown, if it's too large to be read at once.
+    /// Such a document is read-only.
+    large_file: Option<LargeFile>,
+    /// The query highlighted in the rows, which is highlighted again whenever the rows are.
+    highlighted_query: Option<Query>,
+    /// The single match highlighted, by where it starts and its length in graphemes.
+    highlighted_match: Option<(Position, usize)>,
 }
 
 impl Document {
+    /// Opens a file, detecting its encoding.
+    ///
     /// # Errors
     /// Returns an error if the file can't be read.
     pub fn open(filename: &str) -> Result<Self, Error> {
-        let content = fs::read_to_string(filename)?;
+        if is_large(filename)? {
+            return Self::open_large(filename);
+        }
+        let bytes = fs::read(filename)?;
+        let (content, encoding) = FileEncoding::detect(&bytes);
+        let mut document = Self::from_content(filename, &content, encoding);
+        document.disk_state = DiskState::of(Path::new(filename), &bytes);
+        document.read_only = !is_writable(filename);
+        Ok(document)
+    }
+
+    /// Opens a file in the given encoding.
+    ///
+    /// # Errors
+    /// Returns an error if the file can't be read.
+    pub fn open_with_encoding(filename: &str, encoding: FileEncoding) -> Result<Self, Error> {
+        if is_large(filename)? {
+            return Err(Error::other("the file is too large to be decoded at once"));
+        }
+        let bytes = fs::read(filename)?;
+        let (content, encoding) = encoding.decode(&bytes);
+        let mut document = Self::from_content(filename, &content, encoding);
+        document.disk_state = DiskState::of(Path::new(filename), &bytes);
+        document.read_only = !is_writable(filename);
+        Ok(document)
+    }
+
+    /// Opens a file too large to be read at once, whose rows are read as they are shown and which
+    /// can only be viewed.
+    fn open_large(filename: &str) -> Result<Self, Error> {
+        let mut document = Self::from_content(filename, "", FileEncoding::default());
+        document.large_file = Some(LargeFile::open(filename)?);
+        document.disk_state = DiskState::of_large(Path::new(filename));
+        document.read_only = true;
+        Ok(document)
+    }
+
+    /// Whether the lines of a large file are still being counted.
+    #[must_use]
+    pub fn is_indexing(&self) -> bool {
+        self.large_file
+            .as_ref()
+            .is_some_and(|large_file| !large_file.is_indexed())
+    }
+
+    /// Makes sure that the rows in `range` can be looked at, which only takes reading them from
+    /// the disk for a large file. Only these rows are highlighted then.
+    pub fn load_rows(&mut self, range: Range<usize>) {
+        let Some(large_file) = &mut self.large_file else {
+            return;
+        };
+        if large_file.load(range) {
+            self.highlight_held_rows();
+            self.highlight_search(self.held_rows());
+        }
+    }
+
+    /// An empty document for a file that doesn't exist yet, which is created when first saved.
+    #[must_use]
+    pub fn new(filename: &str) -> Self {
+        Self::from_content(filename, "", FileEncoding::default())
+    }
+
+    fn from_content(filename: &str, content: &str, encoding: FileEncoding) -> Self {
         let file_type = FileType::from(filename);
-        let mut rows = Vec::new();
-        for value in content.lines() {
-            let row = Row::from(value);
-            rows.push(row);
+        let lacks_final_newline = !content.is_empty() && !content.ends_with('\n');
+        let (mut lf_count, mut crlf_count) = (0usize, 0usize);
+        let mut rows: RowTree = content
+            .split_inclusive('\n')
+            .map(|line| {
+                let (text, ending) = if let Some(text) = line.strip_suffix("\r\n") {
+                    crlf_count = crlf_count.saturating_add(1);
+                    (text, LineEnding::Crlf)
+                } else if let Some(text) = line.strip_suffix('\n') {
+                    lf_count = lf_count.saturating_add(1);
+                    (text, LineEnding::Lf)
+                } else {
+                    (line, LineEnding::Lf)
+                };
+                let mut row = Row::from(text);
+                row.set_ending(ending);
+                row
+            })
+            .collect();
+        let line_ending = if crlf_count > lf_count {
+            LineEnding::Crlf
+        } else {
+            LineEnding::Lf
+        };
+        // The last row gets a row break of the file's style once a row is added after it.
+        if lacks_final_newline {
+            if let Some(row) = rows.get_mut(rows.len().saturating_sub(1)) {
+                row.set_ending(line_ending);
+            }
         }
-        Ok(Self {
+        Self {
             rows,
             filename: Some(filename.to_owned()),
             is_dirty: false,
             file_type,
-        })
+            history: History::default(),
+            recent_edits: Vec::new(),
+            row_breaks: RowBreaks {
+                ending: line_ending,
+                is_mixed: lf_count > 0 && crlf_count > 0,
+                lacks_final_newline,
+            },
+            encoding,
+            disk_state: None,
+            read_only: false,
+            large_file: None,
+            highlighted_query: None,
+            highlighted_match: None,
+        }
+    }
+
+    #[must_use]
+    pub fn is_read_only(&self) -> bool {
+        self.read_only
+    }
+
+    /// A large file stays read-only.
+    pub fn set_read_only(&mut self, read_only: bool) {
+        self.read_only = read_only || self.large_file.is_some();
+    }
+
+    /// Whether another program changed the file since it was last read or written. A file that
+    /// was only touched counts as unchanged, unless it's a large file, and so does a deleted one,
+    /// which has nothing to lose.
+    /// A touched file is taken as the one last read, so that it isn't read again on every check.
+    pub fn is_changed_on_disk(&mut self) -> bool {
+        let (Some(filename), Some(state)) = (&self.filename, &self.disk_state) else {
+            return false;
+        };
+        let Ok(metadata) = fs::metadata(filename) else {
+            return false;
+        };
+        if metadata.len() == state.len && metadata.modified().ok() == state.modified {
+            return false;
+        }
+        // A large file is too large to be read to tell whether it was only touched.
+        if self.large_file.is_some() {
+            return true;
+        }
+        let Some(new) = fs::read(filename)
+            .ok()
+            .and_then(|bytes| DiskState::of(Path::new(filename), &bytes))
+        else {
+            return false;
+        };
+        if new.hash != state.hash {
+            return true;
+        }
+        self.disk_state = Some(new);
+        false
+    }
+
+    /// Takes the file as it is now as the one last read, so that its changes are no longer
+    /// reported.
+    pub fn acknowledge_disk_changes(&mut self) {
+        if let Some(filename) = &self.filename {
+            let path = Path::new(filename);
+            if self.large_file.is_some() {
+                self.disk_state = DiskState::of_large(path);
+                return;
+            }
+            self.disk_state = fs::read(path)
+                .ok()
+                .and_then(|bytes| DiskState::of(path, &bytes));
+        }
+    }
+
+    #[must_use]
+    pub fn encoding(&self) -> &'static str {
+        self.encoding.name()
+    }
+
+    /// Sets the encoding the document is saved in.
+    pub fn set_encoding(&mut self, encoding: FileEncoding) {
+        if self.read_only {
+            return;
+        }
+        self.encoding = encoding;
+        self.mark_unsaved();
+    }
+
+    /// The line ending of the document, or `None` if it has both.
+    #[must_use]
+    pub fn line_ending(&self) -> Option<LineEnding> {
+        (!self.row_breaks.is_mixed).then_some(self.row_breaks.ending)
+    }
+
+    /// Makes every row break use `ending`.
+    pub fn convert_line_endings(&mut self, ending: LineEnding) {
+        if self.read_only {
+            return;
+        }
+        for row in self.rows.iter_mut() {
+            row.set_ending(ending);
+        }
+        self.row_breaks.ending = ending;
+        self.row_breaks.is_mixed = false;
+        self.mark_unsaved();
+    }
+
+    /// An empty row that ends with the line ending of the document.
+    fn new_row(&self) -> Row {
+        let mut row = Row::default();
+        row.set_ending(self.row_breaks.ending);
+        row
     }
 
     #[must_use]
@@ -40,116 +324,708 @@ impl Document {
 
     #[must_use]
     pub fn row(&self, index: usize) -> Option<&Row> {
-        self.rows.get(index)
+        match &self.large_file {
+            Some(large_file) => large_file.row(index),
+            None => self.rows.get(index),
+        }
     }
 
     /// Whether the document is empty or no documents have been loaded.
     #[must_use]
     pub fn is_empty(&self) -> bool {
-        self.rows.is_empty()
+        self.len() == 0
     }
 
+    /// The number of rows, which for a large file are the ones counted so far.
     #[must_use]
     pub fn len(&self) -> usize {
-        self.rows.len()
+        self.large_file
+            .as_ref()
+            .map_or_else(|| self.rows.len(), LargeFile::len)
     }
 
-    /// # Panics
-    /// Panics if trying to insert pass the end of the row.
     pub fn insert(&mut self, at: &Position, c: char) {
-        if at.y > self.len() {
+        if at.y > self.len() || self.read_only {
             return;
         }
+        let text = c.to_string();
+        let new_row = self.insert_raw(at, &text);
+        self.record(Edit::Insert {
+            at: at.clone(),
+            text,
+            new_row,
+        });
         self.is_dirty = true;
-        if c == '\n' {
-            self.insert_newline(at);
-            return;
+    }
+
+    /// Inserts either a single grapheme or a row break without recording it in the history; undoing
+    /// the deletion of a range may insert any text, with rows separated by `\n`.
+    /// Returns whether a new row was appended to the end of the document.
+    fn insert_raw(&mut self, at: &Position, text: &str) -> bool {
+        if text == "\n" {
+            return self.insert_newline(at);
+        }
+        if let Some((first, rest)) = text.split_once('\n') {
+            self.insert_rows(at, first, rest);
+            return false;
         }
         // If adding to the end of the file, push a new row with such
-        // character as the first character; otherwise, take that row
-        // and insert to the corresponding position.
+        // text as its content; otherwise, take that row and insert to
+        // the corresponding position.
         if at.y == self.len() {
-            let mut row = Row::default();
-            row.insert(0, c);
+            let mut row = self.new_row();
+            row.insert_str(0, text);
             self.rows.push(row);
+            true
         } else {
-            #[allow(clippy::indexing_slicing)]
-            let row = &mut self.rows[at.y];
-            row.insert(at.x, c);
+            if let Some(row) = self.rows.get_mut(at.y) {
+                row.insert_str(at.x, text);
+            }
+            false
         }
     }
 
-    /// # Notes
-    /// The dirty flag is not touched.
-    fn insert_newline(&mut self, at: &Position) {
-        if at.y > self.len() {
+    /// Inserts text spanning several rows at `at`: `first` goes onto the end of the row there, and
+    /// the rest of that row follows the last line of `rest`.
+    fn insert_rows(&mut self, at: &Position, first: &str, rest: &str) {
+        let line_ending = self.row_breaks.ending;
+        let Some(row) = self.rows.get_mut(at.y) else {
             return;
+        };
+        let tail = row.split(at.x);
+        row.insert_str(at.x, first);
+        row.set_ending(line_ending);
+        let mut y = at.y;
+        let mut lines = rest.split('\n').peekable();
+        while let Some(line) = lines.next() {
+            y = y.saturating_add(1);
+            let mut row = Row::from(line);
+            if lines.peek().is_some() {
+                row.set_ending(line_ending);
+            } else {
+                row.append(&tail);
+            }
+            self.rows.insert(y, row);
         }
+    }
+
+    /// Returns whether a new row was appended to the end of the document.
+    fn insert_newline(&mut self, at: &Position) -> bool {
         // NOTE: Navigating to one row below the last is allowed.
         if at.y == self.len() {
-            self.rows.push(Row::default());
-            return;
+            self.rows.push(self.new_row());
+            return true;
         }
         // This works even at the end of a line, with `new_row` being empty.
-        #[allow(clippy::indexing_slicing)]
-        let curr_row = &mut self.rows[at.y];
-        let new_row = curr_row.split(at.x);
-        #[allow(clippy::arithmetic_side_effects)]
-        self.rows.insert(at.y + 1, new_row);
+        let line_ending = self.row_breaks.ending;
+        if let Some(curr_row) = self.rows.get_mut(at.y) {
+            let new_row = curr_row.split(at.x);
+            curr_row.set_ending(line_ending);
+            self.rows.insert(at.y.saturating_add(1), new_row);
+        }
+        false
+    }
+
+    /// Inserts a row break as a single change, carrying over the leading whitespace of the row to
+    /// the new one, plus one level of indentation after an opening bracket. Returns where the
+    /// cursor goes.
+    pub fn insert_newline_indented(&mut self, at: &Position) -> Position {
+        let Some(row) = self.rows.get(at.y) else {
+            self.insert(at, '\n');
+            return Position {
+                x: 0,
+                y: at.y.saturating_add(1),
+            };
+        };
+        let before = row.slice(0, at.x);
+        let indent: String = before.chars().take_while(|c| c.is_whitespace()).collect();
+        let mut text = format!("\n{indent}");
+        let mut closing_row = None;
+        if let Some(unit) = self.file_type.indent_unit() {
+            let last = before.trim_end().chars().last();
+            if let Some(&(_, closer)) = BRACKETS.iter().find(|(opener, _)| Some(*opener) == last) {
+                text.push_str(unit);
+                // The closing bracket of an empty pair goes onto a row of its own, back at the
+                // indentation of the opening one.
+                if row.slice(at.x, row.len()).starts_with(closer) {
+                    closing_row = Some(format!("\n{indent}"));
+                }
+            }
+        }
+        self.begin_change();
+        let cursor = self.insert_str(at, &text);
+        if let Some(closing_row) = closing_row {
+            self.insert_str(&cursor, &closing_row);
+        }
+        self.end_change();
+        cursor
+    }
+
+    /// Inserts a character at `at`. A closing bracket typed on a blank row first takes away one
+    /// level of indentation, as a single change with it. Returns where the character was inserted.
+    pub fn insert_dedented(&mut self, at: &Position, c: char) -> Position {
+        let unit_len = self
+            .file_type
+            .indent_unit()
+            .map_or(0, |unit| unit.graphemes(true).count());
+        let blank_row = self
+            .rows
+            .get(at.y)
+            .map(|row| row.slice(0, row.len()))
+            .filter(|row| !row.is_empty() && row.chars().all(char::is_whitespace));
+        let Some(row) =
+            blank_row.filter(|_| unit_len > 0 && BRACKETS.iter().any(|(_, closer)| *closer == c))
+        else {
+            self.insert(at, c);
+            return at.clone();
+        };
+        // A tab is a level of its own.
+        let removed = if row.ends_with('\t') {
+            1
+        } else {
+            cmp::min(
+                unit_len,
+                row.graphemes(true).rev().take_while(|g| *g == " ").count(),
+            )
+        };
+        let len = row.graphemes(true).count();
+        let start = Position {
+            x: len.saturating_sub(removed),
+            y: at.y,
+        };
+        let at = Position {
+            x: cmp::min(at.x, start.x),
+            y: at.y,
+        };
+        self.begin_change();
+        self.delete_range(&start, &Position { x: len, y: at.y });
+        self.insert(&at, c);
+        self.end_change();
+        at
+    }
+
+    /// Inserts a character at `at` like `insert_dedented`, along with its closing partner if it
+    /// opens a pair in this filetype. Returns where the character was inserted.
+    pub fn insert_paired(&mut self, at: &Position, c: char) -> Position {
+        let Some(closer) = self.closing_partner(at, c) else {
+            return self.insert_dedented(at, c);
+        };
+        self.begin_change();
+        self.insert(at, c);
+        self.insert(
+            &Position {
+                x: at.x.saturating_add(1),
+                y: at.y,
+            },
+            closer,
+        );
+        self.end_change();
+        at.clone()
+    }
+
+    /// The closing partner to insert along with `c` at `at`, if any. Pairs are only inserted
+    /// where they enclose nothing yet, i.e. in front of whitespace, a closing character or the end
+    /// of the row.
+    fn closing_partner(&self, at: &Position, c: char) -> Option<char> {
+        let pairs = self.file_type.auto_pairs();
+        let &(_, closer) = pairs.iter().find(|(opener, _)| *opener == c)?;
+        let row = self.rows.get(at.y);
+        let next = row.and_then(|row| row.slice(at.x, row.len()).chars().next());
+        let is_free = next.is_none_or(|next| {
+            next.is_whitespace() || pairs.iter().any(|(_, closer)| *closer == next)
+        });
+        // A quote right after a word most likely closes a string instead.
+        let previous = row.and_then(|row| row.slice(0, at.x).chars().last());
+        let ends_word = c == closer && previous.is_some_and(char::is_alphanumeric);
+        (is_free && !ends_word).then_some(closer)
+    }
+
+    /// Whether typing `c` at `at` should step over the same closing character instead of inserting
+    /// another one.
+    #[must_use]
+    pub fn skips_over(&self, at: &Position, c: char) -> bool {
+        self.file_type
+            .auto_pairs()
+            .iter()
+            .any(|(_, closer)| *closer == c)
+            && self
+                .rows
+                .get(at.y)
+                .is_some_and(|row| row.slice(at.x, at.x.saturating_add(1)) == c.to_string())
+    }
+
+    /// Whether `at` is between the opening and closing characters of an empty pair.
+    #[must_use]
+    pub fn is_in_empty_pair(&self, at: &Position) -> bool {
+        let (Some(row), Some(before)) = (self.rows.get(at.y), at.x.checked_sub(1)) else {
+            return false;
+        };
+        let pair = row.slice(before, at.x.saturating_add(1));
+        self.file_type
+            .auto_pairs()
+            .iter()
+            .any(|(opener, closer)| pair == format!("{opener}{closer}"))
     }
 
-    /// # Panics
-    /// Panics if trying to delete pass the end of the row.
-    #[allow(clippy::indexing_slicing, clippy::arithmetic_side_effects)]
     pub fn delete(&mut self, at: &Position) {
-        if at.y >= self.len() {
+        if self.read_only {
             return;
         }
-        self.is_dirty = true;
+        if let Some(text) = self.delete_raw(at) {
+            self.record(Edit::Delete {
+                at: at.clone(),
+                text,
+            });
+            self.is_dirty = true;
+        }
+    }
+
+    /// Deletes without recording it in the history. Returns the deleted grapheme, or a row break if
+    /// the next row was joined; `None` if there is nothing to delete.
+    #[allow(clippy::arithmetic_side_effects)]
+    fn delete_raw(&mut self, at: &Position) -> Option<String> {
+        let this_row = self.rows.get(at.y)?;
         // If deleting at the end of the row, the next row is moved up.
-        if at.x == self.rows[at.y].len()
+        if at.x == this_row.len()
         // not last row
         && at.y + 1 < self.len()
         {
-            #[allow(clippy::arithmetic_side_effects)]
-            let next_row = self.rows.remove(at.y + 1);
-            let this_row = &mut self.rows[at.y];
-            this_row.append(&next_row);
+            let next_row = self.rows.remove(at.y + 1)?;
+            self.rows.get_mut(at.y)?.append(&next_row);
+            Some("\n".to_owned())
+        } else {
+            self.rows.get_mut(at.y)?.delete(at.x)
+        }
+    }
+
+    /// Inserts a string as a single change. Returns the position right after the inserted text.
+    pub fn insert_str(&mut self, at: &Position, text: &str) -> Position {
+        let mut at = at.clone();
+        self.begin_change();
+        for c in text.chars() {
+            let old_len = self.row(at.y).map_or(0, Row::len);
+            self.insert(&at, c);
+            if c == '\n' {
+                at = Position {
+                    x: 0,
+                    y: at.y.saturating_add(1),
+                };
+            } else {
+                // A combining character doesn't add a grapheme.
+                let new_len = self.row(at.y).map_or(0, Row::len);
+                at.x = at.x.saturating_add(new_len.saturating_sub(old_len));
+            }
+        }
+        self.end_change();
+        at
+    }
+
+    /// Replaces the `len` graphemes at `at` by `replacement` as a single change. Returns the
+    /// position right after the replacement.
+    pub fn replace(&mut self, at: &Position, len: usize, replacement: &str) -> Position {
+        self.begin_change();
+        let end = Position {
+            x: at.x.saturating_add(len),
+            y: at.y,
+        };
+        self.delete_range(at, &end);
+        let end = self.insert_str(at, replacement);
+        self.end_change();
+        end
+    }
+
+    /// The text from `start` up until `end`, with rows separated by `\n`.
+    #[must_use]
+    pub fn text_range(&self, start: &Position, end: &Position) -> String {
+        // The rows of a large file may have been unloaded since they were on the screen.
+        let read_rows;
+        let rows: Vec<&Row> = if let Some(large_file) = &self.large_file {
+            read_rows = large_file.read_rows(start.y..end.y.saturating_add(1));
+            read_rows.iter().collect()
+        } else {
+            (start.y..=end.y).map_while(|y| self.rows.get(y)).collect()
+        };
+        let mut text = String::new();
+        for (y, row) in (start.y..).zip(rows) {
+            if y > start.y {
+                text.push('\n');
+            }
+            let from = if y == start.y { start.x } else { 0 };
+            let to = if y == end.y { end.x } else { row.len() };
+            text.push_str(row.slice(from, to));
+        }
+        text
+    }
+
+    /// Deletes everything from `start` up until `end` as a single change.
+    pub fn delete_range(&mut self, start: &Position, end: &Position) {
+        if self.read_only {
+            return;
+        }
+        let text = self.delete_range_raw(start, end);
+        if text.is_empty() {
+            return;
+        }
+        // Sealed on its own, so that deleting right after it isn't undone along with it.
+        self.begin_change();
+        self.record(Edit::Delete {
+            at: start.clone(),
+            text,
+        });
+        self.end_change();
+        self.is_dirty = true;
+    }
+
+    /// Deletes the range at once without recording it in the history. Returns the deleted text.
+    fn delete_range_raw(&mut self, start: &Position, end: &Position) -> String {
+        // An end past the last row stands for the end of the document.
+        let last_y = self.len().saturating_sub(1);
+        let end = if end.y > last_y {
+            Position {
+                x: self.rows.get(last_y).map_or(0, Row::len),
+                y: last_y,
+            }
         } else {
-            let this_row = &mut self.rows[at.y];
-            this_row.delete(at.x);
+            end.clone()
+        };
+        if (start.y, start.x) >= (end.y, end.x) {
+            return String::new();
+        }
+        let text = self.text_range(start, &end);
+        let Some(end_row) = self.rows.get_mut(end.y) else {
+            return String::new();
+        };
+        let tail = end_row.split(end.x);
+        for _ in start.y..end.y {
+            self.rows.remove(start.y.saturating_add(1));
+        }
+        if let Some(row) = self.rows.get_mut(start.y) {
+            let _deleted = row.split(start.x);
+            row.append(&tail);
+        }
+        text
+    }
+
+    /// Deletes the row at `y` along with its row break as a single change.
+    pub fn delete_row(&mut self, y: usize) {
+        let Some(row) = self.rows.get(y) else {
+            return;
+        };
+        let end = Position { x: row.len(), y };
+        if y.saturating_add(1) < self.len() {
+            self.delete_range(
+                &Position { x: 0, y },
+                &Position {
+                    x: 0,
+                    y: y.saturating_add(1),
+                },
+            );
+        } else if let Some(prev_y) = y.checked_sub(1) {
+            // The last row has no row break of its own, so the one before it goes instead.
+            let prev_len = self.rows.get(prev_y).map_or(0, Row::len);
+            self.delete_range(
+                &Position {
+                    x: prev_len,
+                    y: prev_y,
+                },
+                &end,
+            );
+        } else {
+            self.delete_range(&Position { x: 0, y }, &end);
+        }
+    }
+
+    /// Inserts a copy of the row at `y` below it as a single change.
+    pub fn duplicate_row(&mut self, y: usize) {
+        let Some(row) = self.rows.get(y) else {
+            return;
+        };
+        let at = Position { x: row.len(), y };
+        let text = format!("\n{}", row.slice(0, row.len()));
+        self.insert_str(&at, &text);
+    }
+
+    /// Swaps the row at `y` with the one below it as a single change. Returns whether there was a
+    /// row below to swap with.
+    pub fn swap_rows(&mut self, y: usize) -> bool {
+        if self.read_only || self.rows.get(y.saturating_add(1)).is_none() {
+            return false;
         }
+        self.swap_rows_raw(y);
+        self.record(Edit::SwapRows {
+            at: Position { x: 0, y },
+        });
+        self.is_dirty = true;
+        true
+    }
+
+    /// Moves the row below `y` in front of it without recording it in the history.
+    fn swap_rows_raw(&mut self, y: usize) {
+        if let Some(next_row) = self.rows.remove(y.saturating_add(1)) {
+            self.rows.insert(y, next_row);
+        }
+    }
+
+    /// Joins the row at `y` with the one below it as a single change, collapsing the whitespace
+    /// around the row break into a single space. Returns where the rows were joined, or `None` if
+    /// there is no row below.
+    pub fn join_rows(&mut self, y: usize) -> Option<Position> {
+        let below = y.saturating_add(1);
+        let row = self.rows.get(y)?;
+        let next_row = self.rows.get(below)?;
+        let is_space = |grapheme: &&str| grapheme.chars().all(char::is_whitespace);
+        let trailing = row
+            .slice(0, row.len())
+            .graphemes(true)
+            .rev()
+            .take_while(is_space)
+            .count();
+        let leading = next_row
+            .slice(0, next_row.len())
+            .graphemes(true)
+            .take_while(is_space)
+            .count();
+        let start = Position {
+            x: row.len().saturating_sub(trailing),
+            y,
+        };
+        let end = Position {
+            x: leading,
+            y: below,
+        };
+        // A blank row has no text to keep apart from the other one.
+        let needs_space = start.x > 0 && leading < next_row.len();
+        self.begin_change();
+        self.delete_range(&start, &end);
+        if needs_space {
+            self.insert(&start, ' ');
+        }
+        self.end_change();
+        Some(start)
+    }
+
+    /// Marks a change that isn't in the history as unsaved, which undoing edits doesn't revert.
+    fn mark_unsaved(&mut self) {
+        self.is_dirty = true;
+        self.history.mark_unsaved();
+    }
+
+    fn record(&mut self, edit: Edit) {
+        self.recent_edits.push(edit.clone());
+        self.history.record(edit);
+    }
+
+    /// The edits made since this was last called.
+    pub fn take_recent_edits(&mut self) -> Vec<Edit> {
+        mem::take(&mut self.recent_edits)
     }
 
+    /// Groups all edits until the matching `end_change` into a single undoable change.
+    pub fn begin_change(&mut self) {
+        self.history.begin_change();
+    }
+
+    pub fn end_change(&mut self) {
+        self.history.end_change();
+    }
+
+    /// Reverts the last change. Returns where the cursor should be placed, or `None` if there is
+    /// nothing to undo.
+    pub fn undo(&mut self) -> Option<Position> {
+        if self.read_only {
+            return None;
+        }
+        let (edits, cursor) = self.history.undo()?;
+        for edit in edits.iter().rev() {
+            match edit {
+                Edit::Insert { new_row: true, .. } => {
+                    self.rows.pop();
+                }
+                Edit::Insert { at, .. } => {
+                    self.delete_raw(at);
+                }
+                Edit::Delete { at, text } => {
+                    self.insert_raw(at, text);
+                }
+                Edit::SwapRows { at } => self.swap_rows_raw(at.y),
+            }
+            self.recent_edits.push(edit.inverse());
+        }
+        self.is_dirty = !self.history.is_at_saved();
+        Some(cursor)
+    }
+
+    /// Performs the last undone change again. Returns where the cursor should be placed, or `None`
+    /// if there is nothing to redo.
+    pub fn redo(&mut self) -> Option<Position> {
+        if self.read_only {
+            return None;
+        }
+        let (edits, cursor) = self.history.redo()?;
+        for edit in &edits {
+            match edit {
+                Edit::Insert { at, text, .. } => {
+                    self.insert_raw(at, text);
+                }
+                Edit::Delete { at, .. } => {
+                    self.delete_range_raw(at, &edit.text_end());
+                }
+                Edit::SwapRows { at } => self.swap_rows_raw(at.y),
+            }
+            self.recent_edits.push(edit.clone());
+        }
+        self.is_dirty = !self.history.is_at_saved();
+        Some(cursor)
+    }
+
+    /// Writes the document to its file. The content goes to a temporary file next to it first,
+    /// which then replaces the file at once, so that the file is never left half-written.
+    ///
     /// # Errors
-    /// Returns an error if the file doesn't exist and can't be created, or can't
-    /// be written.
+    /// Returns an error if the content can't be encoded, or the file can't be written.
     pub fn save(&mut self) -> Result<(), Error> {
-        if let Some(filename) = &self.filename {
-            let mut file = fs::File::create(filename)?;
-            self.file_type = FileType::from(filename);
-            for row in &mut self.rows {
-                file.write_all(row.as_bytes())?;
-                file.write_all(b"\n")?;
+        if let Some(filename) = self.filename.clone() {
+            self.save_as(&filename)?;
+        }
+        Ok(())
+    }
+
+    /// Writes the document to `filename`, which becomes its file. The file type is taken from
+    /// the new name.
+    ///
+    /// # Errors
+    /// Returns an error if the content can't be encoded, or the file can't be written.
+    pub fn save_as(&mut self, filename: &str) -> Result<(), Error> {
+        if self.large_file.is_some() {
+            // A large file is never changed, so it's only ever saved to where it already is.
+            if self.filename.as_deref() == Some(filename) {
+                return Ok(());
             }
-            self.is_dirty = false;
+            return Err(Error::other("a large file can only be copied"));
         }
+        let bytes = self.encoding.encode(&self.content())?;
+        write_atomically(Path::new(filename), &bytes)?;
+        // The swap file goes by the old name.
+        self.remove_swap_file();
+        self.filename = Some(filename.to_owned());
+        self.disk_state = DiskState::of(Path::new(filename), &bytes);
+        self.file_type = FileType::from(filename);
+        self.is_dirty = false;
+        self.history.mark_saved();
         Ok(())
     }
 
+    /// Writes a copy of the document to `filename`, leaving the document as it is.
+    ///
+    /// # Errors
+    /// Returns an error if the content can't be encoded, or the file can't be written.
+    pub fn write_copy(&self, filename: &str) -> Result<(), Error> {
+        if let (Some(_), Some(source)) = (&self.large_file, &self.filename) {
+            return fs::copy(source, filename).map(|_copied| ());
+        }
+        let bytes = self.encoding.encode(&self.content())?;
+        write_atomically(Path::new(filename), &bytes)
+    }
+
+    /// The text of the document as it is written to the file, before encoding.
+    fn content(&self) -> String {
+        let mut content = String::new();
+        let last = self.len().saturating_sub(1);
+        for (y, row) in self.rows.iter().enumerate() {
+            content.push_str(row.slice(0, row.len()));
+            if y < last || !self.row_breaks.lacks_final_newline {
+                content.push_str(row.ending().as_str());
+            }
+        }
+        content
+    }
+
+    /// Whether a swap file with unsaved edits was left behind for `filename`.
+    #[must_use]
+    pub fn has_swap_file(filename: &str) -> bool {
+        swap_path(filename).exists()
+    }
+
+    /// Writes the unsaved document to its swap file, from which it can be recovered if the editor
+    /// doesn't quit cleanly. The swap file is always in UTF-8.
+    ///
+    /// # Errors
+    /// Returns an error if the swap file can't be written.
+    pub fn write_swap_file(&self) -> Result<(), Error> {
+        match &self.filename {
+            Some(filename) => fs::write(swap_path(filename), self.content()),
+            None => Ok(()),
+        }
+    }
+
+    pub fn remove_swap_file(&self) {
+        if let Some(filename) = &self.filename {
+            // There is none unless the document was modified.
+            let _removed = fs::remove_file(swap_path(filename));
+        }
+    }
+
+    /// Opens a file with the unsaved edits from its swap file, keeping the encoding of the file.
+    ///
+    /// # Errors
+    /// Returns an error if the swap file can't be read.
+    pub fn recover(filename: &str) -> Result<Self, Error> {
+        let content = fs::read_to_string(swap_path(filename))?;
+        let original = Self::open(filename).ok();
+        let encoding = original
+            .as_ref()
+            .map_or_else(FileEncoding::default, |original| original.encoding);
+        let mut document = Self::from_content(filename, &content, encoding);
+        document.read_only = original.as_ref().is_some_and(|original| original.read_only);
+        document.disk_state = original.and_then(|original| original.disk_state);
+        document.mark_unsaved();
+        Ok(document)
+    }
+
+    /// The index of the first row that differs from `other`, or `None` if both are the same.
+    #[must_use]
+    pub fn first_difference(&self, other: &Self) -> Option<usize> {
+        let rows = self.rows.iter().zip(other.rows.iter());
+        rows.zip(0..)
+            .find(|((row, other_row), _)| row.as_bytes() != other_row.as_bytes())
+            .map(|(_, y)| y)
+            .or_else(|| (self.len() != other.len()).then(|| cmp::min(self.len(), other.len())))
+    }
+
     #[must_use]
     pub fn is_dirty(&self) -> bool {
         self.is_dirty
     }
 
-    /// Find the first occurrence of a query after a given position.
+    /// Whether a large file is still being searched in the background, in which case finding a
+    /// match has to be asked for again once it's done.
+    #[must_use]
+    pub fn is_searching(&self) -> bool {
+        self.large_file
+            .as_ref()
+            .is_some_and(LargeFile::is_searching)
+    }
+
+    /// Stops searching a large file in the background.
+    pub fn cancel_search(&self) {
+        if let Some(large_file) = &self.large_file {
+            large_file.cancel_search();
+        }
+    }
+
+    /// Find the first match of a query at a given position or after it. Returns where it starts
+    /// along with its length in graphemes. For a large file, `None` is also returned while it's
+    /// being searched in the background.
     #[must_use]
-    pub fn find_after(&self, query: &str, after: &Position) -> Option<Position> {
+    pub fn find_after(&self, query: &Query, after: &Position) -> Option<(Position, usize)> {
+        if let Some(large_file) = &self.large_file {
+            return large_file.find(query, after, true).flatten();
+        }
         // NOTE: The start row is skipped if `after` exceeds the row length.
         let mut x = after.x;
         for (y, row) in self.rows.iter().enumerate().skip(after.y) {
-            if let Some(x) = row.find_after(query, x) {
-                return Some(Position { x, y });
+            if let Some(found) = row.find_after(query, x) {
+                return Some((Position { x: found.start, y }, found.len()));
             }
             // Only the start row is affected by the `after` position.
             x = 0;
@@ -157,47 +1033,241 @@ impl Document {
         None
     }
 
-    /// Find the last occurrence of a query before a given position.
+    /// Find the last match of a query that starts before a given position. Returns where it
+    /// starts along with its length in graphemes.
     #[must_use]
-    pub fn rfind_before(&self, query: &str, before: &Position) -> Option<Position> {
-        let mut x = before.x;
-        #[allow(clippy::indexing_slicing)]
-        for (y, row) in self
+    pub fn rfind_before(&self, query: &Query, before: &Position) -> Option<(Position, usize)> {
+        if let Some(large_file) = &self.large_file {
+            return large_file.find(query, before, false).flatten();
+        }
+        // The rows up until `before`, from the bottom up.
+        let last = cmp::min(before.y.saturating_add(1), self.len());
+        let rows = self
             .rows
             .iter()
-            .enumerate()
-            .take(before.y.saturating_add(1) /* first n, one-based */)
             .rev()
-        {
-            if let Some(x) = row.rfind_before(query, x) {
-                return Some(Position { x, y });
-            }
+            .skip(self.len().saturating_sub(last))
+            .zip((0..last).rev());
+        for (row, y) in rows {
             // Only the start row is affected by the `before` position.
-            x = self.rows[y.saturating_sub(1)].len();
+            let x = if y == before.y { before.x } else { row.len() };
+            if let Some(found) = row.rfind_before(query, x) {
+                return Some((Position { x: found.start, y }, found.len()));
+            }
         }
         None
     }
 
     /// Highlight the document until a given row. Note that the highlight of a row is only affected by the previous rows.
     pub fn highlight_until(&mut self, until: usize) {
+        // The rows of a large file are highlighted as they are loaded.
+        if self.large_file.is_some() {
+            return;
+        }
         let mut highlight_ctx = row::HighlightContext::default();
         self.rows.iter_mut().take(until).for_each(|row| {
             highlight_ctx = row.highlight(self.file_type.highlight_options(), &highlight_ctx);
         });
+        self.highlight_search(0..until);
     }
 
     /// Highlight the query in the entire document.
-    pub fn highlight_query(&mut self, query: &str) {
-        self.rows
-            .iter_mut()
-            .for_each(|row| row.highlight_query(query));
+    pub fn highlight_query(&mut self, query: &Query) {
+        self.highlighted_query = Some(query.clone());
+        self.highlight_search(self.held_rows());
+    }
+
+    /// Highlight a single match of `len` graphemes at `at`.
+    pub fn highlight_match(&mut self, at: &Position, len: usize) {
+        self.highlighted_match = Some((at.clone(), len));
+        self.highlight_search(at.y..at.y.saturating_add(1));
     }
 
     /// Restore the original highlight of the document, particularly after highlighting a query.
     pub fn highlight_restore(&mut self) {
+        self.highlighted_query = None;
+        self.highlighted_match = None;
+        self.highlight_held_rows();
+    }
+
+    /// Highlights the query and the match over the rows in `range`, as the highlight of the
+    /// syntax has left them.
+    fn highlight_search(&mut self, range: Range<usize>) {
+        let query = self.highlighted_query.clone();
+        let current = self.highlighted_match.clone();
+        for y in range {
+            let Some(row) = self.row_mut(y) else {
+                break;
+            };
+            if let Some(query) = &query {
+                row.highlight_query(query);
+            }
+            if let Some((at, len)) = current.as_ref().filter(|(at, _)| at.y == y) {
+                row.highlight_match(at.x..at.x.saturating_add(*len));
+            }
+        }
+    }
+
+    fn highlight_held_rows(&mut self) {
         let mut highlight_ctx = row::HighlightContext::default();
-        self.rows.iter_mut().for_each(|row| {
+        let rows: Box<dyn Iterator<Item = &mut Row>> = match &mut self.large_file {
+            Some(large_file) => Box::new(large_file.loaded_rows_mut()),
+            None => Box::new(self.rows.iter_mut()),
+        };
+        rows.for_each(|row| {
             highlight_ctx = row.highlight(self.file_type.highlight_options(), &highlight_ctx);
         });
     }
+
+    /// The rows in memory, which are only the loaded ones for a large file.
+    fn held_rows(&self) -> Range<usize> {
+        self.large_file
+            .as_ref()
+            .map_or_else(|| 0..self.rows.len(), LargeFile::loaded)
+    }
+
+    fn row_mut(&mut self, index: usize) -> Option<&mut Row> {
+        match &mut self.large_file {
+            Some(large_file) => large_file.row_mut(index),
+            None => self.rows.get_mut(index),
+        }
+    }
+}
+
+/// Whether the file is at least as large as `HECTO_LARGE_FILE_SIZE` says, or 64 MiB.
+fn is_large(filename: &str) -> Result<bool, Error> {
+    let threshold = env::var("HECTO_LARGE_FILE_SIZE")
+        .ok()
+        .and_then(|size| size.parse().ok())
+        .unwrap_or(DEFAULT_LARGE_FILE_SIZE);
+    Ok(fs::metadata(filename)?.len() >= threshold)
+}
+
+/// Whether the file may be written to, going by both its permissions and whether it can actually
+/// be opened for writing, which doesn't change it.
+fn is_writable(filename: &str) -> bool {
+    let permits = fs::metadata(filename).is_ok_and(|metadata| !metadata.permissions().readonly());
+    permits && fs::OpenOptions::new().append(true).open(filename).is_ok()
+}
+
+/// The swap file of `filename`, a hidden file next to it.
+fn swap_path(filename: &str) -> PathBuf {
+    let path = Path::new(filename);
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    path.with_file_name(format!(".{name}.hecto.swp"))
+}
+
+/// Replaces the file at `path` with `bytes` by renaming a temporary file over it. A symlink is
+/// followed so that its target is replaced rather than the link, and the permissions of the
+/// file are kept.
+fn write_atomically(path: &Path, bytes: &[u8]) -> Result<(), Error> {
+    // A file that doesn't exist yet has no link to follow.
+    let path = fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+    let permissions = fs::metadata(&path)
+        .ok()
+        .map(|metadata| metadata.permissions());
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    // The temporary file has to be on the same file system for the rename to be atomic.
+    let temp_path = path.with_file_name(format!(".{name}.hecto-{}.tmp", process::id()));
+    let result =
+        write_synced(&temp_path, bytes, permissions).and_then(|()| fs::rename(&temp_path, &path));
+    if result.is_err() {
+        // Nothing is left to clean up if the file couldn't even be created.
+        let _removed = fs::remove_file(&temp_path);
+    }
+    result
+}
+
+/// Writes `bytes` to a new file and waits until they are on the disk.
+fn write_synced(path: &Path, bytes: &[u8], permissions: Option<Permissions>) -> Result<(), Error> {
+    let mut file = fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(path)?;
+    file.write_all(bytes)?;
+    if let Some(permissions) = permissions {
+        file.set_permissions(permissions)?;
+    }
+    file.sync_all()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A path in the temporary directory for a test to write `content` to.
+    fn temp_file(name: &str, content: &str) -> String {
+        let path = env::temp_dir().join(format!("hecto-{}-{name}", process::id()));
+        let Ok(()) = fs::write(&path, content) else {
+            panic!("can't write {}", path.display());
+        };
+        path.to_string_lossy().into_owned()
+    }
+
+    #[test]
+    fn keeps_recovered_edits_unsaved_after_undo() {
+        let filename = temp_file("recover.txt", "abc\n");
+        let Ok(()) = fs::write(swap_path(&filename), "abcdef\n") else {
+            panic!("can't write the swap file");
+        };
+        let recovered = Document::recover(&filename);
+        let _removed = fs::remove_file(swap_path(&filename));
+        let _removed = fs::remove_file(&filename);
+        let Ok(mut document) = recovered else {
+            panic!("can't recover {filename}");
+        };
+        assert!(document.is_dirty());
+        document.insert(&Position { x: 6, y: 0 }, 'g');
+        document.undo();
+        assert!(document.is_dirty());
+        document.redo();
+        assert!(document.is_dirty());
+    }
+
+    #[test]
+    fn keeps_converted_line_endings_unsaved_after_undo() {
+        let filename = temp_file("convert.txt", "abc\r\n");
+        let opened = Document::open(&filename);
+        let _removed = fs::remove_file(&filename);
+        let Ok(mut document) = opened else {
+            panic!("can't open {filename}");
+        };
+        document.convert_line_endings(LineEnding::Lf);
+        document.insert(&Position { x: 3, y: 0 }, 'd');
+        document.undo();
+        assert!(document.is_dirty());
+    }
+
+    #[test]
+    fn keeps_a_new_encoding_unsaved_after_undo() {
+        let filename = temp_file("encoding.txt", "abc\n");
+        let opened = Document::open(&filename);
+        let _removed = fs::remove_file(&filename);
+        let Ok(mut document) = opened else {
+            panic!("can't open {filename}");
+        };
+        let Some(latin1) = FileEncoding::for_label("latin1") else {
+            panic!("latin1 is not a known encoding");
+        };
+        document.set_encoding(latin1);
+        document.insert(&Position { x: 3, y: 0 }, 'd');
+        document.undo();
+        assert!(document.is_dirty());
+    }
+
+    #[test]
+    fn moves_rows_with_their_own_line_endings() {
+        let filename = temp_file("swap.txt", "a\r\nb\nc\n");
+        let opened = Document::open(&filename);
+        let _removed = fs::remove_file(&filename);
+        let Ok(mut document) = opened else {
+            panic!("can't open {filename}");
+        };
+        assert!(document.swap_rows(0));
+        assert_eq!(document.content(), "b\na\r\nc\n");
+        document.undo();
+        assert_eq!(document.content(), "a\r\nb\nc\n");
+        document.redo();
+        assert_eq!(document.content(), "b\na\r\nc\n");
+    }
 }
@@ -1,20 +1,44 @@
+use core::cmp::{self, Ordering, Reverse};
+use core::iter;
+use core::mem;
+use core::ops::Range;
 use std::env;
+use std::fs;
+use std::path::Path;
 use std::time::{Duration, Instant};
 
+use crate::clipboard::Clipboard;
+use crate::completion;
+use crate::encoding::FileEncoding;
+use crate::history::Edit;
+use crate::layout::{Direction, Layout, Rect};
+use crate::search::{self, Query, SearchOptions};
+use crate::terminal::Modifier;
 use crate::Document;
+use crate::LineEnding;
 use crate::Row;
 use crate::Terminal;
-use clipboard::ClipboardContext;
-use clipboard::ClipboardProvider;
 use std::io::Error;
-use termion::color;
 use termion::event::Key;
+use termion::{color, style};
 
 const VERSION: &str = env!("CARGO_PKG_VERSION");
 const STATUS_BG_COLOR: color::Rgb = color::Rgb(239, 239, 239);
 const STATUS_FG_COLOR: color::Rgb = color::Rgb(63, 63, 63);
+/// The status lines of the windows that aren't focused are dimmed.
+const INACTIVE_STATUS_FG_COLOR: color::Rgb = color::Rgb(143, 143, 143);
 /// The number of times the user has to press `Ctrl-Q` to quit.
 const QUIT_TIMES: u8 = 3;
+/// How long the editor waits for a key before doing its checks for when the user is idle.
+const IDLE_INTERVAL: Duration = Duration::from_secs(1);
+/// How often a prompt checks whether a search running in the background is done.
+const SEARCH_POLL_INTERVAL: Duration = Duration::from_millis(50);
+/// How often the swap file is written while there are unsaved changes.
+const SWAP_INTERVAL: Duration = Duration::from_secs(2);
+/// The command-line flags that open the files read-only.
+const READ_ONLY_FLAGS: [&str; 2] = ["--readonly", "-R"];
+/// The tab stop used unless `HECTO_TAB_STOP` is set.
+const DEFAULT_TAB_STOP: usize = 8;
 
 #[derive(Default, Clone, PartialEq)]
 pub struct Position {
@@ -42,15 +66,226 @@ impl StatusMessage {
     }
 }
 
+/// Where the user is in a document, which every window has of its own.
+#[derive(Default, Clone)]
+struct View {
+    offset: Position,
+    wrap_offset: usize,
+    cursor_position: Position,
+    secondary_cursors: Vec<Position>,
+    anchor: Option<Position>,
+}
+
+impl View {
+    /// Moves the positions along with an edit made in another window, so that they stay at the
+    /// same text.
+    fn follow(&mut self, edit: &Edit) {
+        self.cursor_position = edit.shift(&self.cursor_position);
+        for cursor in &mut self.secondary_cursors {
+            *cursor = edit.shift(cursor);
+        }
+        if let Some(anchor) = &mut self.anchor {
+            *anchor = edit.shift(anchor);
+        }
+    }
+}
+
+/// What a window shows, borrowed from wherever it's kept in order to draw it.
+struct Frame<'editor> {
+    document: &'editor Document,
+    /// The part of the screen where the text is shown, above the status line.
+    area: Rect,
+    offset: &'editor Position,
+    wrap_offset: usize,
+    cursor_position: &'editor Position,
+    secondary_cursors: &'editor [Position],
+    anchor: Option<&'editor Position>,
+    tab_stop: usize,
+    soft_wrap: bool,
+}
+
+impl Frame<'_> {
+    /// The row shown on each screen line, along with the screen columns of it that are shown.
+    /// Without soft wrap, every row takes one line and is cut to the width of the window.
+    fn screen_lines(&self) -> Vec<(usize, Range<usize>)> {
+        let Rect { width, height, .. } = self.area;
+        if !self.soft_wrap {
+            let columns = self.offset.x..self.offset.x.saturating_add(width);
+            return (0..height)
+                .map(|term_row| (self.offset.y.saturating_add(term_row), columns.clone()))
+                .collect();
+        }
+        let mut lines = Vec::new();
+        let mut line = self.wrap_offset;
+        for y in self.offset.y.. {
+            let Some(row) = self.document.row(y) else {
+                break;
+            };
+            let points = wrap_points(self.document, y, width, self.tab_stop);
+            for (index, start) in points.iter().enumerate().skip(line) {
+                if lines.len() >= height {
+                    return lines;
+                }
+                let start = row.column_of(*start, self.tab_stop);
+                let end = points
+                    .get(index.saturating_add(1))
+                    .map_or(start.saturating_add(width), |end| {
+                        row.column_of(*end, self.tab_stop)
+                    });
+                lines.push((y, start..end));
+            }
+            line = 0;
+        }
+        // The lines past the end of the document.
+        let past_end = self.offset.y.max(self.document.len());
+        let remaining = height.saturating_sub(lines.len());
+        lines.extend((past_end..past_end.saturating_add(remaining)).map(|y| (y, 0..width)));
+        lines
+    }
+
+    /// If the row exists, draw it.
+    /// Otherwise, draw a tilde, meaning that row is not part of the document and
+    /// can't contain any text.
+    fn draw_rows(&self, screen_lines: &[(usize, Range<usize>)]) {
+        let height = screen_lines.len();
+        let area = self.area;
+        for (term_row, (y, columns)) in screen_lines.iter().enumerate() {
+            // The line is blanked first, as the windows beside it are not to be cleared.
+            let line_start = Position {
+                x: area.x,
+                y: area.y.saturating_add(term_row),
+            };
+            Terminal::cursor_position(&line_start);
+            print!("{}", " ".repeat(area.width));
+            Terminal::cursor_position(&line_start);
+            // If such row exists, draw it.
+            #[allow(clippy::integer_division)]
+            if let Some(row) = self.document.row(*y) {
+                self.draw_row(row, *y, columns.clone());
+            } else if self.document.is_empty() && term_row == height / 3 {
+                // XXX: Should we draw the welcome message if we do open an empty file?
+                self.draw_welcome_message();
+            } else {
+                print!("~");
+            }
+        }
+    }
+
+    fn draw_welcome_message(&self) {
+        let mut welcome_msg = format!("Hecto editor -- version {VERSION}");
+        let term_width = self.area.width;
+        let msg_len = welcome_msg.len();
+        // The padding is the number of spaces to add to the left of the message.
+        #[allow(clippy::integer_division)]
+        let padding = term_width.saturating_sub(msg_len) / 2;
+        let spaces = " ".repeat(padding.saturating_add(1 /* for ~ */));
+        welcome_msg = format!("~{spaces}{welcome_msg}");
+        welcome_msg.truncate(term_width);
+        print!("{welcome_msg}");
+    }
+
+    /// Draws the screen columns of the row at `y` in `columns`.
+    fn draw_row(&self, row: &Row, y: usize, columns: Range<usize>) {
+        let Range { start, end } = columns;
+        // The secondary cursors are drawn as one-grapheme selections.
+        let mut selections: Vec<Range<usize>> = self.selection_in_row(y, row).into_iter().collect();
+        selections.extend(
+            self.secondary_cursors
+                .iter()
+                .filter(|cursor| cursor.y == y)
+                .map(|cursor| cursor.x..cursor.x.saturating_add(1)),
+        );
+        let mut rendered = row.render(start, end, &selections, self.tab_stop);
+        // A cursor at the end of the row has no grapheme to highlight, so a space stands in for it.
+        let row_end = row.column_of(row.len(), self.tab_stop);
+        if (start..end).contains(&row_end)
+            && self
+                .secondary_cursors
+                .iter()
+                .any(|cursor| cursor.y == y && cursor.x == row.len())
+        {
+            let end_cursor = format!("{} {}", style::Invert, style::NoInvert);
+            rendered.push_str(&end_cursor);
+        }
+        print!("{rendered}");
+    }
+
+    /// The part of the row at `y` that is selected.
+    fn selection_in_row(&self, y: usize, row: &Row) -> Option<Range<usize>> {
+        let (start, end) = ordered_selection(self.anchor?, self.cursor_position)?;
+        if y < start.y || y > end.y {
+            return None;
+        }
+        let from = if y == start.y { start.x } else { 0 };
+        let to = if y == end.y { end.x } else { row.len() };
+        Some(from..to)
+    }
+}
+
+/// An open document along with where the user was in it.
+#[derive(Default)]
+struct Buffer {
+    document: Document,
+    /// Where the user was when the buffer was last switched away from in a window.
+    view: View,
+}
+
+impl From<Document> for Buffer {
+    fn from(document: Document) -> Self {
+        Self {
+            document,
+            ..Self::default()
+        }
+    }
+}
+
+/// A part of the screen showing a buffer.
+struct Window {
+    buffer: usize,
+    view: View,
+}
+
 pub struct Editor {
     should_quit: bool,
     terminal: Terminal,
+    /// The open buffers. The document and search state of the one in the focused window are left
+    /// empty, as they are kept in the fields below.
+    buffers: Vec<Buffer>,
+    current_buffer: usize,
+    /// The windows on the screen. The view of the focused one is left empty, as it's kept in the
+    /// fields below.
+    windows: Vec<Window>,
+    focused_window: usize,
+    layout: Layout,
     document: Document,
-    /// Where of the file the user is currently scrolled to.
+    /// Where of the file the user is currently scrolled to. `x` is a screen column rather than a
+    /// grapheme index, since tabs take up more than one column.
     offset: Position,
+    /// The multiple of screen columns that tabs expand to.
+    tab_stop: usize,
+    /// Whether long rows are wrapped onto several screen lines instead of scrolling horizontally.
+    soft_wrap: bool,
+    /// The first screen line of the row at `offset.y` that is visible in soft-wrap mode.
+    wrap_offset: usize,
     cursor_position: Position,
+    /// Further cursors, in the order they were added. Edits at the cursor are made at each of
+    /// them as well, while the view follows `cursor_position` only.
+    secondary_cursors: Vec<Position>,
+    /// The other end of the selection, which spans from here to the cursor.
+    anchor: Option<Position>,
+    /// How queries are matched, which stays as toggled for the next searches in every buffer.
+    search_options: SearchOptions,
+    /// The query last compiled from a prompt, along with the input and options it was compiled
+    /// from, so that it's compiled once per change of them rather than on every use.
+    compiled_query: Option<(String, SearchOptions, Result<Query, regex::Error>)>,
     status_message: StatusMessage,
     quit_times: u8,
+    clipboard: Clipboard,
+    /// The contents of the clipboard if they are whole lines copied without a selection, which
+    /// are pasted above the current line rather than at the cursor.
+    line_wise_clipboard: Option<String>,
+    /// When the swap file was last written.
+    swap_written_at: Instant,
 }
 
 impl Default for Editor {
@@ -58,43 +293,707 @@ impl Default for Editor {
         let args: Vec<String> = env::args().collect();
         let mut initial_status =
             String::from("HELP: Ctrl-F = find | Ctrl-S = save | Ctrl-Q = quit");
-        let document = if let Some(filename) = args.get(1) {
-            if let Ok(doc) = Document::open(filename) {
-                doc
+        let (flags, filenames): (Vec<&String>, Vec<&String>) = args
+            .iter()
+            .skip(1)
+            .partition(|arg| READ_ONLY_FLAGS.contains(&arg.as_str()));
+        let mut documents = Vec::new();
+        for filename in filenames {
+            if let Ok(mut doc) = Document::open(filename) {
+                if !flags.is_empty() {
+                    doc.set_read_only(true);
+                }
+                documents.push(doc);
             } else {
                 initial_status = format!("ERR: Could not open file: {filename}");
-                Document::default()
             }
-        } else {
-            Document::default()
-        };
+        }
+        let mut documents = documents.into_iter();
+        let document = documents.next().unwrap_or_default();
         Self {
             should_quit: false,
             #[allow(clippy::expect_used)]
             terminal: Terminal::new().expect("Failed to initialize terminal"),
+            buffers: iter::once(Buffer::default())
+                .chain(documents.map(Buffer::from))
+                .collect(),
+            current_buffer: 0,
+            windows: vec![Window {
+                buffer: 0,
+                view: View::default(),
+            }],
+            focused_window: 0,
+            layout: Layout::default(),
             document,
             offset: Position::default(),
+            tab_stop: env::var("HECTO_TAB_STOP")
+                .ok()
+                .and_then(|tab_stop| tab_stop.parse().ok())
+                .filter(|tab_stop| *tab_stop > 0)
+                .unwrap_or(DEFAULT_TAB_STOP),
+            soft_wrap: false,
+            wrap_offset: 0,
             // top-left corner
             cursor_position: Position::default(),
+            secondary_cursors: Vec::new(),
+            anchor: None,
+            search_options: SearchOptions::default(),
+            compiled_query: None,
             status_message: StatusMessage::from(initial_status),
             quit_times: QUIT_TIMES,
+            clipboard: Clipboard::default(),
+            line_wise_clipboard: None,
+            swap_written_at: Instant::now(),
         }
     }
 }
 
 impl Editor {
     pub fn run(&mut self) {
+        for index in 0..self.buffers.len() {
+            self.switch_buffer(index);
+            if let Err(e) = &self.offer_recovery() {
+                die(e);
+            }
+        }
+        self.switch_buffer(0);
         loop {
             // NOTE: The screen is refreshed before quitting.
             if let Err(e) = &self.refresh_screen() {
+                self.write_swap_file();
                 die(e);
             }
             if self.should_quit {
+                self.document.remove_swap_file();
+                for buffer in &self.buffers {
+                    buffer.document.remove_swap_file();
+                }
                 break;
             }
-            if let Err(e) = &self.process_keypress() {
+            let result = match self.terminal.read_key_timeout(IDLE_INTERVAL) {
+                Ok(Some(key)) => {
+                    self.process_keypress(key);
+                    Ok(())
+                }
+                Ok(None) => self.check_while_idle(),
+                Err(e) => Err(e),
+            };
+            if let Err(e) = &result {
+                self.write_swap_file();
                 die(e);
             }
+            // Reading keys times out while idle, so the last edits are written as well, without
+            // waiting for another key.
+            if self.document.is_dirty() && self.swap_written_at.elapsed() >= SWAP_INTERVAL {
+                self.write_swap_file();
+            }
+        }
+    }
+
+    /// The document of the buffer at `index`, which is kept in the editor if it's the current one.
+    fn document_of(&self, index: usize) -> Option<&Document> {
+        if index == self.current_buffer {
+            return Some(&self.document);
+        }
+        self.buffers.get(index).map(|buffer| &buffer.document)
+    }
+
+    fn document_of_mut(&mut self, index: usize) -> Option<&mut Document> {
+        if index == self.current_buffer {
+            return Some(&mut self.document);
+        }
+        self.buffers
+            .get_mut(index)
+            .map(|buffer| &mut buffer.document)
+    }
+
+    /// What the window at `index` shows in `area`, borrowed to be drawn.
+    fn frame(&self, index: usize, area: Rect) -> Option<Frame<'_>> {
+        let window = self.windows.get(index)?;
+        let document = self.document_of(window.buffer)?;
+        let frame = if index == self.focused_window {
+            // The view of the focused window is kept in the editor.
+            Frame {
+                document,
+                area,
+                offset: &self.offset,
+                wrap_offset: self.wrap_offset,
+                cursor_position: &self.cursor_position,
+                secondary_cursors: &self.secondary_cursors,
+                anchor: self.anchor.as_ref(),
+                tab_stop: self.tab_stop,
+                soft_wrap: self.soft_wrap,
+            }
+        } else {
+            let view = &window.view;
+            Frame {
+                document,
+                area,
+                offset: &view.offset,
+                wrap_offset: view.wrap_offset,
+                cursor_position: &view.cursor_position,
+                secondary_cursors: &view.secondary_cursors,
+                anchor: view.anchor.as_ref(),
+                tab_stop: self.tab_stop,
+                soft_wrap: self.soft_wrap,
+            }
+        };
+        Some(frame)
+    }
+
+    /// Moves the cursors of the other windows showing the current buffer along with the edits made
+    /// in it, and so does the view of it kept for when it's switched back to.
+    fn follow_edits(&mut self) {
+        let edits = self.document.take_recent_edits();
+        if edits.is_empty() {
+            return;
+        }
+        let (current, focused) = (self.current_buffer, self.focused_window);
+        let windows = self
+            .windows
+            .iter_mut()
+            .enumerate()
+            .filter(|(index, window)| *index != focused && window.buffer == current)
+            .map(|(_, window)| &mut window.view);
+        let views = windows.chain(self.buffers.get_mut(current).map(|buffer| &mut buffer.view));
+        for view in views {
+            for edit in &edits {
+                view.follow(edit);
+            }
+        }
+    }
+
+    /// The view of the focused window, leaving it empty.
+    fn take_view(&mut self) -> View {
+        View {
+            offset: mem::take(&mut self.offset),
+            wrap_offset: mem::take(&mut self.wrap_offset),
+            cursor_position: mem::take(&mut self.cursor_position),
+            secondary_cursors: mem::take(&mut self.secondary_cursors),
+            anchor: self.anchor.take(),
+        }
+    }
+
+    /// Shows `view` in the focused window. Its positions are kept within the document, which may
+    /// have been edited in another window since.
+    fn show_view(&mut self, view: View) {
+        self.offset = view.offset;
+        self.wrap_offset = view.wrap_offset;
+        self.cursor_position = self.clamp_position(&view.cursor_position);
+        self.secondary_cursors = view
+            .secondary_cursors
+            .into_iter()
+            .map(|cursor| self.clamp_position(&cursor))
+            .collect();
+        self.anchor = view.anchor.map(|anchor| self.clamp_position(&anchor));
+        self.dedup_cursors();
+    }
+
+    /// The nearest position to `position` that is within the document.
+    fn clamp_position(&self, position: &Position) -> Position {
+        let y = cmp::min(position.y, self.document.len());
+        Position {
+            x: cmp::min(position.x, self.row_len(y)),
+            y,
+        }
+    }
+
+    /// Makes the buffer at `index` the current one, keeping the document of the one before in its
+    /// entry.
+    fn make_current(&mut self, index: usize) {
+        if index == self.current_buffer || index >= self.buffers.len() {
+            return;
+        }
+        if let Some(buffer) = self.buffers.get_mut(self.current_buffer) {
+            buffer.document = mem::take(&mut self.document);
+        }
+        if let Some(buffer) = self.buffers.get_mut(index) {
+            self.document = mem::take(&mut buffer.document);
+        }
+        self.current_buffer = index;
+    }
+
+    /// Shows the buffer at `index` in the focused window, where it was left off.
+    fn switch_buffer(&mut self, index: usize) {
+        if index == self.current_buffer || index >= self.buffers.len() {
+            return;
+        }
+        // The swap file of a hidden buffer is not written until it's shown again.
+        if self.document.is_dirty() {
+            self.write_swap_file();
+        }
+        let view = self.take_view();
+        if let Some(buffer) = self.buffers.get_mut(self.current_buffer) {
+            buffer.view = view;
+        }
+        self.make_current(index);
+        let view = self
+            .buffers
+            .get(index)
+            .map(|buffer| buffer.view.clone())
+            .unwrap_or_default();
+        self.show_view(view);
+        if let Some(window) = self.windows.get_mut(self.focused_window) {
+            window.buffer = index;
+        }
+    }
+
+    /// Focuses the window at `index`, keeping the view of the one before in its entry.
+    fn focus_window(&mut self, index: usize) {
+        if index == self.focused_window {
+            return;
+        }
+        let Some(buffer) = self.windows.get(index).map(|window| window.buffer) else {
+            return;
+        };
+        let view = self.take_view();
+        if let Some(window) = self.windows.get_mut(self.focused_window) {
+            window.view = view;
+        }
+        self.make_current(buffer);
+        self.focused_window = index;
+        let view = self
+            .windows
+            .get_mut(index)
+            .map(|window| mem::take(&mut window.view))
+            .unwrap_or_default();
+        self.show_view(view);
+    }
+
+    /// The windows in the order they appear on the screen.
+    fn window_order(&self) -> Vec<usize> {
+        let (windows, _) = self.layout.arrange(self.screen());
+        windows.into_iter().map(|(index, _)| index).collect()
+    }
+
+    /// Moves the focus to the next window on the screen, wrapping around.
+    fn cycle_windows(&mut self) {
+        let order = self.window_order();
+        let position = order
+            .iter()
+            .position(|index| *index == self.focused_window)
+            .unwrap_or(0);
+        let next = order
+            .get(position.saturating_add(1))
+            .or_else(|| order.first())
+            .copied()
+            .unwrap_or(0);
+        if self.document.is_dirty() {
+            self.write_swap_file();
+        }
+        self.focus_window(next);
+    }
+
+    /// Splits the focused window in two, both showing the same view of the buffer, and focuses
+    /// the new one.
+    fn split_window(&mut self, direction: Direction) {
+        let area = self.area();
+        #[allow(clippy::integer_division)]
+        let first_size = match direction {
+            // The upper window keeps its status line.
+            Direction::Horizontal => area.height.saturating_add(1) / 2,
+            Direction::Vertical => area.width / 2,
+        };
+        if first_size < 2 {
+            self.status_message =
+                StatusMessage::from("The window is too small to split.".to_owned());
+            return;
+        }
+        let view = self.take_view();
+        self.show_view(view.clone());
+        let new_window = self.windows.len();
+        self.windows.push(Window {
+            buffer: self.current_buffer,
+            view,
+        });
+        self.layout
+            .split(self.focused_window, new_window, direction, first_size);
+        self.focus_window(new_window);
+    }
+
+    /// Closes the focused window, moving the focus to the next one.
+    fn close_window(&mut self) {
+        if self.windows.len() <= 1 {
+            self.status_message = StatusMessage::from("This is the only window.".to_owned());
+            return;
+        }
+        let closed = self.focused_window;
+        self.cycle_windows();
+        self.windows.remove(closed);
+        self.layout.remove(closed);
+        if self.focused_window > closed {
+            self.focused_window = self.focused_window.saturating_sub(1);
+        }
+    }
+
+    fn resize_window(&mut self, grow: bool) {
+        if !self.layout.resize(self.focused_window, grow, self.screen()) {
+            self.status_message = StatusMessage::from("This is the only window.".to_owned());
+        }
+    }
+
+    /// The part of the screen taken up by the windows, which is all but the message bar.
+    fn screen(&self) -> Rect {
+        Rect {
+            x: 0,
+            y: 0,
+            width: self.terminal.size().width as usize,
+            // The status bar of the terminal size is that of a window.
+            height: (self.terminal.size().height as usize).saturating_add(1),
+        }
+    }
+
+    /// The part of the screen where the focused window shows the text, above its status line.
+    fn area(&self) -> Rect {
+        let (windows, _) = self.layout.arrange(self.screen());
+        let mut area = windows
+            .into_iter()
+            .find(|(index, _)| *index == self.focused_window)
+            .map_or_else(|| self.screen(), |(_, area)| area);
+        area.height = area.height.saturating_sub(1);
+        area
+    }
+
+    /// Switches to the next buffer, or the previous one if not `forward`, wrapping around.
+    fn cycle_buffers(&mut self, forward: bool) {
+        let count = self.buffers.len();
+        let index = if forward {
+            self.current_buffer.saturating_add(1)
+        } else {
+            self.current_buffer
+                .checked_sub(1)
+                .unwrap_or_else(|| count.saturating_sub(1))
+        };
+        self.switch_buffer(if index >= count { 0 } else { index });
+        self.status_message = StatusMessage::from(format!(
+            "Buffer {}/{count}: {}",
+            self.current_buffer.saturating_add(1),
+            buffer_name(&self.document)
+        ));
+    }
+
+    /// The document of every buffer, in order.
+    fn documents(&self) -> impl Iterator<Item = &Document> {
+        self.buffers.iter().enumerate().map(|(index, buffer)| {
+            if index == self.current_buffer {
+                &self.document
+            } else {
+                &buffer.document
+            }
+        })
+    }
+
+    /// Opens a file in a new buffer, or switches to its buffer if it's open already. A file that
+    /// doesn't exist yet gets an empty buffer.
+    fn open_file(&mut self) {
+        let Some(filename) = self.prompt_path("Open (Tab to complete): ") else {
+            return;
+        };
+        let open = self.documents().position(|document| {
+            document
+                .filename
+                .as_ref()
+                .is_some_and(|open| is_same_file(open, &filename))
+        });
+        if let Some(index) = open {
+            self.switch_buffer(index);
+            return;
+        }
+        let document = if Path::new(&filename).exists() {
+            match Document::open(&filename) {
+                Ok(document) => document,
+                Err(error) => {
+                    self.status_message =
+                        StatusMessage::from(format!("ERR: Could not open file: {error}"));
+                    return;
+                }
+            }
+        } else {
+            self.status_message = StatusMessage::from(format!("New file: {filename}"));
+            Document::new(&filename)
+        };
+        // The empty buffer the editor starts with when no file is given is taken over.
+        if self.document.filename.is_none() && !self.document.is_dirty() && self.document.is_empty()
+        {
+            self.replace_document(document);
+        } else {
+            self.buffers.push(Buffer::from(document));
+            self.switch_buffer(self.buffers.len().saturating_sub(1));
+        }
+        // Failing to read the keys is noticed again by the main loop.
+        let _asked = self.offer_recovery();
+    }
+
+    /// Prompts for a path, which Tab completes, and returns it with `~` expanded.
+    fn prompt_path(&mut self, prompt: &str) -> Option<String> {
+        let complete = |editor: &mut Self, key: Key, input: &mut String| {
+            if key == Key::Char('\t') {
+                editor.complete_path(input);
+            }
+        };
+        let input = self.prompt(prompt, complete).unwrap_or(None)?;
+        Some(completion::expand_home(&input))
+    }
+
+    /// Prompts for a path to write the document to, asking before overwriting another file.
+    fn prompt_target(&mut self, prompt: &str) -> Option<String> {
+        let filename = self.prompt_path(prompt)?;
+        let is_own_file = self
+            .document
+            .filename
+            .as_ref()
+            .is_some_and(|own| is_same_file(own, &filename));
+        if !is_own_file
+            && Path::new(&filename).exists()
+            && !self
+                .confirm(&format!("{filename} already exists. Overwrite it?"))
+                .unwrap_or(false)
+        {
+            return None;
+        }
+        Some(filename)
+    }
+
+    /// Asks a yes-or-no question, which anything but `y` answers with no.
+    /// # Errors
+    /// Returns an error if the user input can't be read.
+    fn confirm(&mut self, question: &str) -> Result<bool, Error> {
+        self.status_message = StatusMessage::from(format!("{question} (y/N)"));
+        self.refresh_screen()?;
+        let answer = matches!(self.terminal.read_key()?.0, Key::Char('y' | 'Y'));
+        self.status_message.clear();
+        Ok(answer)
+    }
+
+    /// Completes the path in `input` as far as it's unambiguous. Once it can't be completed any
+    /// further, the candidates are listed to pick one.
+    fn complete_path(&mut self, input: &mut String) {
+        let candidates = completion::candidates(input);
+        let common = completion::common_prefix(&candidates);
+        if common.len() > input.len() {
+            *input = common;
+        } else if candidates.len() > 1 {
+            let picked = self
+                .pick("Complete (Enter to choose, ESC to cancel)", &candidates, 0)
+                .unwrap_or(None);
+            if let Some(candidate) = picked.and_then(|index| candidates.get(index)) {
+                input.clone_from(candidate);
+            }
+        }
+    }
+
+    /// Lists the buffers to pick one to switch to.
+    fn pick_buffer(&mut self) {
+        let items: Vec<String> = self
+            .documents()
+            .enumerate()
+            .map(|(index, document)| {
+                let modified = if document.is_dirty() {
+                    " (modified)"
+                } else {
+                    ""
+                };
+                format!(
+                    "{}: {}{modified}",
+                    index.saturating_add(1),
+                    buffer_name(document)
+                )
+            })
+            .collect();
+        let picked = self
+            .pick(
+                "Switch to buffer (Enter to switch, ESC to cancel)",
+                &items,
+                self.current_buffer,
+            )
+            .unwrap_or(None);
+        if let Some(index) = picked {
+            self.switch_buffer(index);
+        }
+    }
+
+    /// Lets the user pick one of `items` with the arrow keys, starting at `selected`, in place of
+    /// the text. `None` is returned if the user cancels.
+    /// # Errors
+    /// Returns an error if the user input can't be read.
+    fn pick(
+        &mut self,
+        prompt: &str,
+        items: &[String],
+        mut selected: usize,
+    ) -> Result<Option<usize>, Error> {
+        let Rect { width, height, .. } = self.screen();
+        self.status_message = StatusMessage::from(prompt.to_owned());
+        let picked = loop {
+            Terminal::cursor_hide();
+            Terminal::cursor_position(&Position::default());
+            // The list scrolls along when the selected item is past the bottom of the screen.
+            let first = selected.saturating_sub(height.saturating_sub(1));
+            for term_row in 0..height {
+                Terminal::clear_current_line();
+                let index = first.saturating_add(term_row);
+                if let Some(item) = items.get(index) {
+                    let item: String = item.chars().take(width).collect();
+                    if index == selected {
+                        println!("{}{item}{}\r", style::Invert, style::NoInvert);
+                    } else {
+                        println!("{item}\r");
+                    }
+                } else {
+                    println!("~\r");
+                }
+            }
+            self.draw_message_bar();
+            Terminal::flush()?;
+            match self.terminal.read_key()?.0 {
+                Key::Up => selected = selected.saturating_sub(1),
+                Key::Down if selected.saturating_add(1) < items.len() => {
+                    selected = selected.saturating_add(1);
+                }
+                Key::Char('\n') => break Some(selected),
+                Key::Esc => break None,
+                _ => (),
+            }
+        };
+        self.status_message.clear();
+        Terminal::cursor_show();
+        Ok(picked)
+    }
+
+    fn write_swap_file(&mut self) {
+        self.swap_written_at = Instant::now();
+        if let Err(error) = self.document.write_swap_file() {
+            self.status_message =
+                StatusMessage::from(format!("WARN: Could not write swap file: {error}"));
+        }
+    }
+
+    fn check_while_idle(&mut self) -> Result<(), Error> {
+        if self.document.is_changed_on_disk() {
+            self.resolve_external_change(false)?;
+        }
+        Ok(())
+    }
+
+    /// Asks what to do about another program having changed the file, either while `saving` or
+    /// when noticed in the meantime. Returns whether the file may be overwritten.
+    fn resolve_external_change(&mut self, saving: bool) -> Result<bool, Error> {
+        let Some(filename) = self.document.filename.clone() else {
+            return Ok(true);
+        };
+        let keep = if saving {
+            "(O)verwrite it"
+        } else {
+            "(K)eep your version"
+        };
+        let mut note = "The file was changed by another program.".to_owned();
+        loop {
+            self.status_message =
+                StatusMessage::from(format!("{note} (R)eload, {keep} or (C)ompare?"));
+            self.refresh_screen()?;
+            match self.terminal.read_key()?.0 {
+                Key::Char('r' | 'R') => {
+                    match Document::open(&filename) {
+                        Ok(document) => {
+                            self.replace_document(document);
+                            self.status_message =
+                                StatusMessage::from("Reloaded the file.".to_owned());
+                        }
+                        Err(error) => {
+                            self.status_message =
+                                StatusMessage::from(format!("ERR: Could not open file: {error}"));
+                        }
+                    }
+                    return Ok(false);
+                }
+                Key::Char('o' | 'O') if saving => return Ok(true),
+                Key::Char('k' | 'K') if !saving => {
+                    self.document.acknowledge_disk_changes();
+                    self.status_message.clear();
+                    return Ok(false);
+                }
+                Key::Char('c' | 'C') => {
+                    let on_disk = Document::open(&filename).unwrap_or_default();
+                    note = match self.document.first_difference(&on_disk) {
+                        Some(y) => format!(
+                            "Yours: {} lines, on disk: {} lines, first difference at line {}.",
+                            self.document.len(),
+                            on_disk.len(),
+                            y.saturating_add(1)
+                        ),
+                        None => "Your version matches the file.".to_owned(),
+                    };
+                }
+                Key::Esc if saving => {
+                    self.status_message = StatusMessage::from("Save aborted.".to_owned());
+                    return Ok(false);
+                }
+                _ => (),
+            }
+        }
+    }
+
+    /// Replaces the document by another version of it, keeping the cursor where it was as far as
+    /// possible.
+    fn replace_document(&mut self, mut document: Document) {
+        // A document made read-only stays that way.
+        if self.document.is_read_only() {
+            document.set_read_only(true);
+        }
+        self.document = document;
+        self.collapse_cursors();
+        self.cursor_position.y = cmp::min(self.cursor_position.y, self.document.len());
+        self.cursor_position.x =
+            cmp::min(self.cursor_position.x, self.row_len(self.cursor_position.y));
+    }
+
+    /// Offers to recover the unsaved edits that a previous session left in a swap file.
+    fn offer_recovery(&mut self) -> Result<(), Error> {
+        let Some(filename) = self.document.filename.clone() else {
+            return Ok(());
+        };
+        if !Document::has_swap_file(&filename) {
+            return Ok(());
+        }
+        let recovered = match Document::recover(&filename) {
+            Ok(recovered) => recovered,
+            Err(error) => {
+                self.status_message =
+                    StatusMessage::from(format!("ERR: Could not read swap file: {error}"));
+                return Ok(());
+            }
+        };
+        let mut note = "Found unsaved edits from a previous session.".to_owned();
+        loop {
+            self.status_message =
+                StatusMessage::from(format!("{note} (R)ecover, (D)iff or discard (X)?"));
+            self.refresh_screen()?;
+            match self.terminal.read_key()?.0 {
+                Key::Char('r' | 'R') => {
+                    self.replace_document(recovered);
+                    self.status_message =
+                        StatusMessage::from("Recovered the unsaved edits.".to_owned());
+                    return Ok(());
+                }
+                Key::Char('d' | 'D') => {
+                    note = match recovered.first_difference(&self.document) {
+                        Some(y) => format!(
+                            "Edits: {} lines, file: {} lines, first difference at line {}.",
+                            recovered.len(),
+                            self.document.len(),
+                            y.saturating_add(1)
+                        ),
+                        None => "The edits match the file.".to_owned(),
+                    };
+                }
+                Key::Char('x' | 'X') => {
+                    self.document.remove_swap_file();
+                    self.status_message =
+                        StatusMessage::from("Discarded the unsaved edits.".to_owned());
+                    return Ok(());
+                }
+                _ => (),
+            }
         }
     }
 
@@ -105,110 +1004,173 @@ impl Editor {
             Terminal::clear_screen();
             println!("Goodbye.\r");
         } else {
-            self.document.highlight_until(
-                self.offset
-                    .y
-                    .saturating_add(self.terminal.size().height as usize),
-            );
-            self.draw_rows();
-            self.draw_status_bar();
+            self.follow_edits();
+            let (windows, separators) = self.layout.arrange(self.screen());
+            let mut cursor_position = Position::default();
+            for (index, mut area) in windows {
+                let is_focused = index == self.focused_window;
+                let Some(buffer) = self.windows.get(index).map(|window| window.buffer) else {
+                    continue;
+                };
+                // The status line is below the text.
+                area.height = area.height.saturating_sub(1);
+                let top = self.frame(index, area).map_or(0, |frame| frame.offset.y);
+                let bottom = top.saturating_add(area.height);
+                if let Some(document) = self.document_of_mut(buffer) {
+                    document.load_rows(top..bottom);
+                    document.highlight_until(bottom);
+                }
+                let Some(frame) = self.frame(index, area) else {
+                    continue;
+                };
+                let screen_lines = frame.screen_lines();
+                frame.draw_rows(&screen_lines);
+                self.draw_status_bar(&frame, buffer, is_focused);
+                if !is_focused {
+                    continue;
+                }
+                let column = self.cursor_column();
+                // The cursor is on the last screen line of its row that starts before it.
+                let screen_line = screen_lines.iter().rposition(|(y, columns)| {
+                    *y == self.cursor_position.y && columns.start <= column
+                });
+                let cursor_pos_relative_to_offset = Position {
+                    x: column.saturating_sub(
+                        screen_lines
+                            .get(screen_line.unwrap_or(0))
+                            .map_or(0, |(_, columns)| columns.start),
+                    ),
+                    y: screen_line.unwrap_or(0),
+                };
+                cursor_position = Position {
+                    x: area.x.saturating_add(cursor_pos_relative_to_offset.x),
+                    y: area.y.saturating_add(cursor_pos_relative_to_offset.y),
+                };
+            }
+            Self::draw_separators(&separators);
             self.draw_message_bar();
-            let cursor_pos_relative_to_offset = Position {
-                x: self.cursor_position.x.saturating_sub(self.offset.x),
-                y: self.cursor_position.y.saturating_sub(self.offset.y),
-            };
-            Terminal::cursor_position(&cursor_pos_relative_to_offset);
+            Terminal::cursor_position(&cursor_position);
         }
         Terminal::cursor_show();
         Terminal::flush()
     }
 
-    /// If the row exists, draw it.
-    /// Otherwise, draw a tilde, meaning that row is not part of the document and
-    /// can't contain any text.
-    fn draw_rows(&self) {
-        let height = self.terminal.size().height;
-        // The last line is kept empty for the status bar.
-        for term_row in 0..height {
-            Terminal::clear_current_line();
-            // If such row exists, draw it.
-            #[allow(clippy::integer_division)]
-            if let Some(row) = self
-                .document
-                .row(self.offset.y.saturating_add(term_row as usize))
-            {
-                self.draw_row(row);
-            } else if self.document.is_empty() && term_row == height / 3 {
-                // XXX: Should we draw the welcome message if we do open an empty file?
-                self.draw_welcome_message();
-            } else {
-                println!("~\r");
+    /// Draws the separators between windows side by side.
+    fn draw_separators(separators: &[Rect]) {
+        for separator in separators {
+            for y in separator.y..separator.y.saturating_add(separator.height) {
+                Terminal::cursor_position(&Position { x: separator.x, y });
+                print!("\u{2502}"); // A box-drawing vertical line.
             }
         }
     }
 
-    fn draw_welcome_message(&self) {
-        let mut welcome_msg = format!("Hecto editor -- version {VERSION}");
-        let term_width = self.terminal.size().width as usize;
-        let msg_len = welcome_msg.len();
-        // The padding is the number of spaces to add to the left of the message.
-        #[allow(clippy::integer_division)]
-        let padding = term_width.saturating_sub(msg_len) / 2;
-        let spaces = " ".repeat(padding.saturating_add(1 /* for ~ */));
-        welcome_msg = format!("~{spaces}{welcome_msg}\r");
-        welcome_msg.truncate(term_width);
-        println!("{welcome_msg}\r");
+    /// The selection, ordered from its start to its end. `None` if nothing is selected.
+    fn selection(&self) -> Option<(Position, Position)> {
+        ordered_selection(self.anchor.as_ref()?, &self.cursor_position)
+    }
+
+    /// Deletes the selected text, if any, and places the cursor where it started.
+    /// Returns whether anything was deleted.
+    fn delete_selection(&mut self) -> bool {
+        let selection = self.selection();
+        self.anchor = None;
+        if let Some((start, end)) = selection {
+            self.document.delete_range(&start, &end);
+            self.cursor_position = start;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Whether the key changes the text of the document, which is not done while it's read-only.
+    fn is_edit(key: Key, modifier: Modifier) -> bool {
+        match key {
+            Key::Char(_) | Key::Delete | Key::Backspace => true,
+            Key::Ctrl(c) => matches!(c, 'k' | 'x' | 'v' | 'z' | 'y' | 'r'),
+            Key::Alt(c) => matches!(c, 'd' | 'j' | 'l' | 'e'),
+            Key::Up | Key::Down => modifier == Modifier::Alt,
+            _ => false,
+        }
     }
 
-    pub fn draw_row(&self, row: &Row) {
-        let width = self.terminal.size().width as usize;
-        let start = self.offset.x;
-        let end = start.saturating_add(width);
-        let row = row.render(start, end);
-        println!("{row}\r");
+    fn toggle_read_only(&mut self) {
+        let read_only = !self.document.is_read_only();
+        self.document.set_read_only(read_only);
+        if self.document.is_read_only() != read_only {
+            self.status_message =
+                StatusMessage::from("A large file can only be viewed.".to_owned());
+            return;
+        }
+        let state = if read_only { "on" } else { "off" };
+        self.status_message = StatusMessage::from(format!("Read-only {state}."));
     }
 
     /// Where the handling logics go.
-    fn process_keypress(&mut self) -> Result<(), Error> {
-        let pressed_key = Terminal::read_key()?;
+    fn process_keypress(&mut self, (pressed_key, modifier): (Key, Modifier)) {
         match pressed_key {
+            _ if self.document.is_read_only() && Self::is_edit(pressed_key, modifier) => {
+                self.status_message = StatusMessage::from(
+                    "The buffer is read-only. Press Alt-O to allow editing.".to_owned(),
+                );
+            }
             // NOTE: Getting a `quit` signal isn't an error.
             Key::Ctrl('q') => {
+                let dirty: Vec<String> = self
+                    .documents()
+                    .filter(|document| document.is_dirty())
+                    .map(buffer_name)
+                    .collect();
                 #[allow(clippy::arithmetic_side_effects)]
-                if self.quit_times > 0 && self.document.is_dirty() {
+                if self.quit_times > 0 && !dirty.is_empty() {
                     self.status_message = StatusMessage::from(format!(
-                        "WARN: File has unsaved changes! Press Ctrl-Q {} more times to quit.",
+                        "WARN: Unsaved changes in {}! Press Ctrl-Q {} more times to quit.",
+                        dirty.join(", "),
                         self.quit_times
                     ));
                     self.quit_times -= 1;
-                    return Ok(());
+                    return;
                 }
                 self.should_quit = true;
             }
-            Key::Ctrl('v') => {
-                if let Ok(mut ctx) = ClipboardContext::new() {
-                    if let Ok(contents) = ctx.get_contents() {
-                        for c in contents.chars() {
-                            self.document.insert(&self.cursor_position, c);
-                            self.move_cursor(Key::Right);
-                        }
-                    }
-                }
-            }
+            Key::Esc => self.collapse_cursors(),
+            Key::Ctrl('d') => self.add_cursor_at_next_occurrence(),
+            Key::Ctrl('k') => self.delete_line(),
+            Key::Alt('d') => self.duplicate_line(),
+            Key::Alt('j') => self.join_lines(),
+            Key::Alt('z') => self.toggle_soft_wrap(),
+            Key::Alt('l') => self.convert_line_endings(),
+            Key::Alt('e') => self.choose_encoding(),
+            Key::Alt('r') => self.reopen_with_encoding(),
+            Key::Alt('.') => self.cycle_buffers(true),
+            Key::Alt(',') => self.cycle_buffers(false),
+            Key::Alt('b') => self.pick_buffer(),
+            Key::Alt('h') => self.split_window(Direction::Horizontal),
+            Key::Alt('v') => self.split_window(Direction::Vertical),
+            Key::Alt('x') => self.close_window(),
+            Key::Alt('w') => self.cycle_windows(),
+            Key::Alt('=') => self.resize_window(true),
+            Key::Alt('-') => self.resize_window(false),
+            Key::Ctrl('c') => self.copy(false),
+            Key::Ctrl('x') => self.copy(true),
+            Key::Ctrl('v') => self.paste(),
+            Key::Ctrl('z') => self.undo(),
+            Key::Ctrl('y') => self.redo(),
             Key::Ctrl('s') => self.save(),
+            Key::Ctrl('o') => self.open_file(),
+            Key::Alt('s') => self.save_as(),
+            Key::Alt('o') => self.toggle_read_only(),
+            Key::Alt('c') => self.write_copy(),
             Key::Ctrl('f') => self.search(),
-            Key::Char(c) => {
-                self.document.insert(&self.cursor_position, c);
-                // So that we don't insert backward.
-                self.move_cursor(Key::Right);
-            }
-            Key::Delete => self.document.delete(&self.cursor_position),
-            // Backspace is a combination of going left and deleting.
-            Key::Backspace => {
-                if self.cursor_position.x > 0 || self.cursor_position.y > 0 {
-                    self.move_cursor(Key::Left);
-                    self.document.delete(&self.cursor_position);
-                }
+            Key::Ctrl('g') => self.go_to_line(),
+            Key::Ctrl('r') => self.replace(),
+            Key::Char(c) => self.at_every_cursor(|editor| editor.insert_char(c)),
+            Key::Delete => self.at_every_cursor(Self::delete_forward),
+            Key::Backspace => self.at_every_cursor(Self::delete_backward),
+            Key::Up | Key::Down if modifier == Modifier::Alt => self.move_line(pressed_key),
+            Key::Up | Key::Down if modifier == Modifier::ShiftAlt => {
+                self.add_cursor_vertically(pressed_key);
             }
             Key::Up
             | Key::Down
@@ -217,7 +1179,19 @@ impl Editor {
             | Key::PageUp
             | Key::PageDown
             | Key::End
-            | Key::Home => self.move_cursor(pressed_key),
+            | Key::Home => {
+                // Moving with Shift held extends the selection; otherwise it is dropped.
+                if modifier == Modifier::Shift {
+                    self.secondary_cursors.clear();
+                    if self.anchor.is_none() {
+                        self.anchor = Some(self.cursor_position.clone());
+                    }
+                    self.move_cursor(pressed_key);
+                } else {
+                    self.anchor = None;
+                    self.move_every_cursor(pressed_key);
+                }
+            }
             _ => (),
         }
         self.scroll();
@@ -226,13 +1200,538 @@ impl Editor {
             self.quit_times = QUIT_TIMES;
             self.status_message.clear();
         }
-        Ok(())
     }
 
-    fn scroll(&mut self) {
+    fn undo(&mut self) {
+        self.collapse_cursors();
+        if let Some(position) = self.document.undo() {
+            self.cursor_position = position;
+        } else {
+            self.status_message = StatusMessage::from("Nothing to undo.".to_owned());
+        }
+    }
+
+    fn redo(&mut self) {
+        self.collapse_cursors();
+        if let Some(position) = self.document.redo() {
+            self.cursor_position = position;
+        } else {
+            self.status_message = StatusMessage::from("Nothing to redo.".to_owned());
+        }
+    }
+
+    /// Switches the line endings between LF and CRLF, or makes them all LF if mixed.
+    fn convert_line_endings(&mut self) {
+        let ending = self
+            .document
+            .line_ending()
+            .map_or(LineEnding::Lf, |line_ending| line_ending.toggled());
+        self.document.convert_line_endings(ending);
+        self.status_message =
+            StatusMessage::from(format!("Converted line endings to {}.", ending.name()));
+    }
+
+    /// Prompts for an encoding by its label, such as `utf-8`, `latin1` or `utf-16le`.
+    fn prompt_encoding(&mut self, prompt: &str) -> Option<FileEncoding> {
+        let label = self.prompt(prompt, |_, _, _| {}).unwrap_or(None)?;
+        let encoding = FileEncoding::for_label(&label);
+        if encoding.is_none() {
+            self.status_message = StatusMessage::from(format!("Unknown encoding: {label}"));
+        }
+        encoding
+    }
+
+    /// Chooses the encoding the document is saved in.
+    fn choose_encoding(&mut self) {
+        if let Some(encoding) = self.prompt_encoding("Save in encoding: ") {
+            self.document.set_encoding(encoding);
+            self.status_message =
+                StatusMessage::from(format!("The file will be saved in {}.", encoding.name()));
+        }
+    }
+
+    /// Reads the file again in another encoding, for when it was detected wrongly.
+    fn reopen_with_encoding(&mut self) {
+        let Some(filename) = self.document.filename.clone() else {
+            return;
+        };
+        if self.document.is_dirty() {
+            self.status_message = StatusMessage::from(
+                "WARN: File has unsaved changes! Save them before reopening.".to_owned(),
+            );
+            return;
+        }
+        let Some(encoding) = self.prompt_encoding("Reopen in encoding: ") else {
+            return;
+        };
+        match Document::open_with_encoding(&filename, encoding) {
+            Ok(document) => {
+                self.replace_document(document);
+                self.status_message =
+                    StatusMessage::from(format!("Reopened in {}.", encoding.name()));
+            }
+            Err(error) => {
+                self.status_message =
+                    StatusMessage::from(format!("ERR: Could not open file: {error}"));
+            }
+        }
+    }
+
+    /// Moves the cursor to the start of a line asked for, or the last line if there are fewer.
+    fn go_to_line(&mut self) {
+        let Ok(Some(line)) = self.prompt("Go to line: ", |_, _, _| {}) else {
+            return;
+        };
+        let Ok(number) = line.trim().parse::<usize>() else {
+            self.status_message = StatusMessage::from(format!("Not a line number: {line}"));
+            return;
+        };
+        self.collapse_cursors();
+        let last = self.document.len().saturating_sub(1);
+        self.cursor_position = Position {
+            x: 0,
+            y: cmp::min(number.saturating_sub(1), last),
+        };
+        if number > self.document.len() && self.document.is_indexing() {
+            self.status_message = StatusMessage::from(format!(
+                "Only {} lines are indexed so far; try again once indexing is done.",
+                self.document.len()
+            ));
+        }
+        self.scroll();
+    }
+
+    /// Drops the selection and the secondary cursors.
+    fn collapse_cursors(&mut self) {
+        self.anchor = None;
+        self.secondary_cursors.clear();
+    }
+
+    fn delete_line(&mut self) {
+        self.collapse_cursors();
+        let y = self.cursor_position.y;
+        self.document.delete_row(y);
+        // Deleting the last row leaves the cursor below the end of the document.
+        self.cursor_position.y = cmp::min(y, self.document.len().saturating_sub(1));
+        self.cursor_position.x =
+            cmp::min(self.cursor_position.x, self.row_len(self.cursor_position.y));
+    }
+
+    /// Duplicates the current line, moving the cursor onto the copy.
+    fn duplicate_line(&mut self) {
+        self.collapse_cursors();
+        if self.cursor_position.y < self.document.len() {
+            self.document.duplicate_row(self.cursor_position.y);
+            self.cursor_position.y = self.cursor_position.y.saturating_add(1);
+        }
+    }
+
+    /// Moves the current line up or down, taking the cursor along.
+    fn move_line(&mut self, key: Key) {
+        self.collapse_cursors();
+        let y = self.cursor_position.y;
+        if key == Key::Up {
+            if let Some(above) = y.checked_sub(1) {
+                if self.document.swap_rows(above) {
+                    self.cursor_position.y = above;
+                }
+            }
+        } else if self.document.swap_rows(y) {
+            self.cursor_position.y = y.saturating_add(1);
+        }
+    }
+
+    /// Joins the current line with the next one, placing the cursor where they were joined.
+    fn join_lines(&mut self) {
+        self.collapse_cursors();
+        if let Some(position) = self.document.join_rows(self.cursor_position.y) {
+            self.cursor_position = position;
+        }
+    }
+
+    /// Copies the selection to the clipboard, or the current line if nothing is selected.
+    /// The copied text is deleted if `cut` is set.
+    fn copy(&mut self, cut: bool) {
+        let (text, is_line_wise) = if let Some((start, end)) = self.selection() {
+            let text = self.document.text_range(&start, &end);
+            // The rows of a large file are read again from the disk, which may fail.
+            let last = cmp::min(end.y, self.document.len().saturating_sub(1));
+            if text.matches('\n').count() < last.saturating_sub(start.y) {
+                self.status_message =
+                    StatusMessage::from("ERR: Could not read the selection.".to_owned());
+                return;
+            }
+            (text, false)
+        } else if let Some(row) = self.document.row(self.cursor_position.y) {
+            (format!("{}\n", row.slice(0, row.len())), true)
+        } else {
+            return;
+        };
+        let backend = self.clipboard.set_contents(&text);
+        self.line_wise_clipboard = is_line_wise.then_some(text);
+        if cut {
+            if is_line_wise {
+                self.document.delete_row(self.cursor_position.y);
+                self.cursor_position.x = 0;
+            } else {
+                self.delete_selection();
+            }
+        }
+        let action = if cut { "Cut" } else { "Copied" };
+        let what = if is_line_wise { "line" } else { "selection" };
+        self.status_message = StatusMessage::from(format!(
+            "{action} {what} to the clipboard ({}).",
+            backend.name()
+        ));
+    }
+
+    /// Pastes from the clipboard at every cursor.
+    fn paste(&mut self) {
+        let (contents, backend) = self.clipboard.get_contents();
+        if contents.is_empty() {
+            self.status_message = StatusMessage::from(format!(
+                "Nothing to paste from the clipboard ({}).",
+                backend.name()
+            ));
+            return;
+        }
+        let is_line_wise = self.line_wise_clipboard.as_ref() == Some(&contents);
+        self.at_every_cursor(|editor| editor.insert_pasted(&contents, is_line_wise));
+        self.status_message =
+            StatusMessage::from(format!("Pasted from the clipboard ({}).", backend.name()));
+    }
+
+    /// Inserts pasted contents at the cursor, replacing the selection.
+    fn insert_pasted(&mut self, contents: &str, is_line_wise: bool) {
+        // The paste is undone as a whole.
+        self.document.begin_change();
+        let had_selection = self.delete_selection();
+        if !had_selection && is_line_wise {
+            // Whole lines go above the current line, with the cursor staying on the same text.
+            let at = Position {
+                x: 0,
+                y: self.cursor_position.y,
+            };
+            let end = self.document.insert_str(&at, contents);
+            self.cursor_position.y = end.y;
+        } else {
+            self.cursor_position = self.document.insert_str(&self.cursor_position, contents);
+        }
+        self.document.end_change();
+    }
+
+    /// Typing replaces the selection, which is undone along with the typed character.
+    fn insert_char(&mut self, c: char) {
+        let has_selection = self.selection().is_some();
+        if has_selection {
+            self.document.begin_change();
+        }
+        self.delete_selection();
+        self.type_char(c);
+        if has_selection {
+            self.document.end_change();
+        }
+    }
+
+    fn type_char(&mut self, c: char) {
+        if c == '\n' {
+            self.cursor_position = self.document.insert_newline_indented(&self.cursor_position);
+            return;
+        }
+        // A closing character is typed over if it's already there.
+        if !self.document.skips_over(&self.cursor_position, c) {
+            self.cursor_position = self.document.insert_paired(&self.cursor_position, c);
+        }
+        // So that we don't insert backward.
+        self.move_cursor(Key::Right);
+    }
+
+    fn delete_forward(&mut self) {
+        let deleted_selection = self.delete_selection();
+        if !deleted_selection {
+            self.document.delete(&self.cursor_position);
+        }
+    }
+
+    /// Backspace is a combination of going left and deleting. Both characters of an empty pair go
+    /// at once.
+    fn delete_backward(&mut self) {
+        if !self.delete_selection() && (self.cursor_position.x > 0 || self.cursor_position.y > 0) {
+            let is_in_empty_pair = self.document.is_in_empty_pair(&self.cursor_position);
+            self.move_cursor(Key::Left);
+            if is_in_empty_pair {
+                let end = Position {
+                    x: self.cursor_position.x.saturating_add(2),
+                    y: self.cursor_position.y,
+                };
+                self.document.delete_range(&self.cursor_position, &end);
+            } else {
+                self.document.delete(&self.cursor_position);
+            }
+        }
+    }
+
+    fn row_len(&self, y: usize) -> usize {
+        self.document.row(y).map_or(0, Row::len)
+    }
+
+    /// Performs `action`, which edits at the cursor and moves it, at every cursor as a single
+    /// change. The cursors are visited from the last in the document to the first, so that an edit
+    /// only changes the text before the cursors visited already, which keep their distance from
+    /// the end of the document in rows and from the end of their row in graphemes.
+    fn at_every_cursor<F>(&mut self, mut action: F)
+    where
+        F: FnMut(&mut Self),
+    {
+        if self.secondary_cursors.is_empty() {
+            action(self);
+            return;
+        }
+        // Each cursor along with its index among the secondary cursors; `None` for the primary one.
+        let mut cursors: Vec<(Position, Option<usize>)> = mem::take(&mut self.secondary_cursors)
+            .into_iter()
+            .enumerate()
+            .map(|(index, cursor)| (cursor, Some(index)))
+            .collect();
+        cursors.push((self.cursor_position.clone(), None));
+        cursors.sort_by_key(|(cursor, _)| Reverse((cursor.y, cursor.x)));
+        let mut visited: Vec<(Position, Option<usize>)> = Vec::new();
+        self.document.begin_change();
+        for (cursor, index) in cursors {
+            let from_end: Vec<(usize, usize)> = visited
+                .iter()
+                .map(|(visited, _)| {
+                    (
+                        self.document.len().saturating_sub(visited.y),
+                        self.row_len(visited.y).saturating_sub(visited.x),
+                    )
+                })
+                .collect();
+            self.cursor_position = cursor;
+            action(self);
+            for ((visited, _), (rows_from_end, from_row_end)) in visited.iter_mut().zip(from_end) {
+                visited.y = self.document.len().saturating_sub(rows_from_end);
+                visited.x = self.row_len(visited.y).saturating_sub(from_row_end);
+            }
+            visited.push((self.cursor_position.clone(), index));
+        }
+        self.document.end_change();
+        visited.sort_by_key(|(_, index)| *index);
+        for (cursor, index) in visited {
+            if index.is_some() {
+                self.secondary_cursors.push(cursor);
+            } else {
+                self.cursor_position = cursor;
+            }
+        }
+        self.dedup_cursors();
+    }
+
+    /// Removes the secondary cursors that ended up at the same place as another cursor.
+    fn dedup_cursors(&mut self) {
+        let mut seen = vec![self.cursor_position.clone()];
+        self.secondary_cursors.retain(|cursor| {
+            if seen.contains(cursor) {
+                false
+            } else {
+                seen.push(cursor.clone());
+                true
+            }
+        });
+    }
+
+    fn move_every_cursor(&mut self, key: Key) {
+        let primary = self.cursor_position.clone();
+        let mut secondary_cursors = mem::take(&mut self.secondary_cursors);
+        for cursor in &mut secondary_cursors {
+            self.cursor_position = cursor.clone();
+            self.move_cursor(key);
+            cursor.clone_from(&self.cursor_position);
+        }
+        self.cursor_position = primary;
+        self.move_cursor(key);
+        self.secondary_cursors = secondary_cursors;
+        self.dedup_cursors();
+    }
+
+    /// Adds a cursor below the lowest cursor, or above the highest one.
+    fn add_cursor_vertically(&mut self, key: Key) {
+        let cursors = iter::once(&self.cursor_position).chain(&self.secondary_cursors);
+        let outermost = if key == Key::Up {
+            cursors.min_by_key(|cursor| (cursor.y, cursor.x))
+        } else {
+            cursors.max_by_key(|cursor| (cursor.y, cursor.x))
+        }
+        .cloned()
+        .unwrap_or_default();
+        let primary = mem::replace(&mut self.cursor_position, outermost);
+        self.move_cursor(key);
+        let added = mem::replace(&mut self.cursor_position, primary);
+        self.anchor = None;
+        self.secondary_cursors.push(added);
+        self.dedup_cursors();
+    }
+
+    /// Adds a cursor at the next whole-word occurrence of the word under the cursor, searching from
+    /// the cursor added last and wrapping around the end of the document.
+    fn add_cursor_at_next_occurrence(&mut self) {
+        let Position { x, y } = self.cursor_position;
+        let Some((word, offset)) = self.document.row(y).and_then(|row| {
+            // The cursor may also be right behind the word.
+            let range = row.word_at(x).or_else(|| row.word_at(x.checked_sub(1)?))?;
+            Some((
+                row.slice(range.start, range.end).to_owned(),
+                x.saturating_sub(range.start),
+            ))
+        }) else {
+            self.status_message = StatusMessage::from("No word under the cursor.".to_owned());
+            return;
+        };
+        let last = self
+            .secondary_cursors
+            .last()
+            .unwrap_or(&self.cursor_position);
+        let after = Position {
+            x: last.x.saturating_sub(offset).saturating_add(1),
+            y: last.y,
+        };
+        // Only the same word counts, not a part of a longer one.
+        let options = SearchOptions {
+            whole_word: true,
+            ..SearchOptions::default()
+        };
+        let Ok(query) = Query::new(&word, options) else {
+            return;
+        };
+        let found = match self.find_waiting(&query, &after) {
+            Some(found) => Some(found),
+            None => self.find_waiting(&query, &Position::default()),
+        };
+        let added = found.map(|(found, _)| Position {
+            x: found.x.saturating_add(offset),
+            y: found.y,
+        });
+        match added {
+            Some(added)
+                if added != self.cursor_position && !self.secondary_cursors.contains(&added) =>
+            {
+                self.anchor = None;
+                self.secondary_cursors.push(added);
+            }
+            _ => {
+                self.status_message =
+                    StatusMessage::from(format!("No more occurrences of {word}."));
+            }
+        }
+    }
+
+    /// The screen column of the cursor within its row.
+    fn cursor_column(&self) -> usize {
+        let Position { x, y } = self.cursor_position;
+        self.document
+            .row(y)
+            .map_or(0, |row| row.column_of(x, self.tab_stop))
+    }
+
+    /// The graphemes at which the screen lines of the row at `y` start in soft-wrap mode.
+    fn wrap_points(&self, y: usize) -> Vec<usize> {
+        wrap_points(&self.document, y, self.area().width, self.tab_stop)
+    }
+
+    /// The screen line of a row that the grapheme at `x` is on, given the row's `wrap_points`.
+    fn screen_line_of(wrap_points: &[usize], x: usize) -> usize {
+        wrap_points
+            .partition_point(|start| *start <= x)
+            .saturating_sub(1)
+    }
+
+    fn toggle_soft_wrap(&mut self) {
+        self.soft_wrap = !self.soft_wrap;
+        self.offset.x = 0;
+        self.wrap_offset = 0;
+        let state = if self.soft_wrap { "on" } else { "off" };
+        self.status_message = StatusMessage::from(format!("Soft wrap {state}."));
+    }
+
+    /// Keeps the cursor within the visible screen lines in soft-wrap mode.
+    fn scroll_wrapped(&mut self) {
+        let height = self.area().height;
+        let y = self.cursor_position.y;
+        let line = Self::screen_line_of(&self.wrap_points(y), self.cursor_position.x);
+        let offset = (self.offset.y, self.wrap_offset);
+        if (y, line) <= offset {
+            (self.offset.y, self.wrap_offset) = (y, line);
+            return;
+        }
+        // Walk up from the cursor until reaching the top of the screen; if the screen height is
+        // walked without reaching it, the cursor is below the screen.
+        let mut top = (y, line);
+        for _ in 1..height {
+            if top == offset {
+                return;
+            }
+            top = match top {
+                (y, 0) => match y.checked_sub(1) {
+                    Some(above) => (above, self.wrap_points(above).len().saturating_sub(1)),
+                    None => break,
+                },
+                (y, line) => (y, line.saturating_sub(1)),
+            };
+        }
+        if top != offset {
+            (self.offset.y, self.wrap_offset) = top;
+        }
+    }
+
+    /// The position one screen line above or below the cursor in soft-wrap mode, at the same
+    /// screen column within the line where possible.
+    fn screen_line_move(&self, key: Key) -> Position {
         let Position { x, y } = self.cursor_position;
-        let width = self.terminal.size().width as usize;
-        let height = self.terminal.size().height as usize;
+        let column_of = |y: usize, at: usize| {
+            self.document
+                .row(y)
+                .map_or(0, |row| row.column_of(at, self.tab_stop))
+        };
+        let points = self.wrap_points(y);
+        let line = Self::screen_line_of(&points, x);
+        let column =
+            column_of(y, x).saturating_sub(column_of(y, points.get(line).copied().unwrap_or(0)));
+        let (y, line, points) = if key == Key::Up {
+            if line > 0 {
+                (y, line.saturating_sub(1), points)
+            } else if let Some(above) = y.checked_sub(1) {
+                let points = self.wrap_points(above);
+                (above, points.len().saturating_sub(1), points)
+            } else {
+                return self.cursor_position.clone();
+            }
+        } else if line.saturating_add(1) < points.len() {
+            (y, line.saturating_add(1), points)
+        } else if y < self.document.len() {
+            let below = y.saturating_add(1);
+            (below, 0, self.wrap_points(below))
+        } else {
+            return self.cursor_position.clone();
+        };
+        let start = column_of(y, points.get(line).copied().unwrap_or(0));
+        let x = self.document.row(y).map_or(0, |row| {
+            row.index_at_column(start.saturating_add(column), self.tab_stop)
+        });
+        // Stay on the screen line rather than going to the start of the next one.
+        let x = points
+            .get(line.saturating_add(1))
+            .map_or(x, |next| cmp::min(x, next.saturating_sub(1)));
+        Position { x, y }
+    }
+
+    fn scroll(&mut self) {
+        if self.soft_wrap {
+            self.scroll_wrapped();
+            return;
+        }
+        let y = self.cursor_position.y;
+        let x = self.cursor_column();
+        let Rect { width, height, .. } = self.area();
 
         // Check if the cursor has moved outside of the visible window,
         // and if so, adjust offset so that the cursor is just inside the visible window.
@@ -250,7 +1749,7 @@ impl Editor {
 
     fn move_cursor(&mut self, key: Key) {
         let Position { mut x, mut y } = self.cursor_position;
-        let term_height = self.terminal.size().height as usize;
+        let term_height = self.area().height;
         // The cursor is allowed to move to the last row of the document.
         let doc_height = self.document.len();
         let mut row_width = if let Some(row) = self.document.row(y) {
@@ -259,6 +1758,9 @@ impl Editor {
             0
         };
         match key {
+            Key::Up | Key::Down if self.soft_wrap => {
+                Position { x, y } = self.screen_line_move(key);
+            }
             Key::Up => y = y.saturating_sub(1),
             Key::Down => {
                 // Prevent the cursor from keep going down after the last row.
@@ -326,45 +1828,82 @@ impl Editor {
         self.cursor_position = Position { x, y };
     }
 
-    fn draw_status_bar(&self) {
-        let modified_indicator = if self.document.is_dirty() {
+    /// Draws the status line of a window showing the buffer at `buffer`, dimmed unless it has the
+    /// `focus` of the user.
+    fn draw_status_bar(&self, frame: &Frame, buffer: usize, focus: bool) {
+        let Frame {
+            document,
+            area,
+            cursor_position,
+            secondary_cursors,
+            ..
+        } = *frame;
+        let modified_indicator = if document.is_dirty() {
             " (modified)"
         } else {
             ""
         };
-        let filename = if let Some(name) = &self.document.filename {
-            let mut name = name.clone();
-            name.truncate(20);
-            name
-        } else {
-            "[No Name]".to_owned()
-        };
-        let mut status = format!(
-            "{filename} - {} lines{modified_indicator}",
-            self.document.len()
-        );
+        let mut filename = buffer_name(document);
+        filename.truncate(20);
+        if document.is_read_only() {
+            filename.push_str(" [RO]");
+        }
+        let mut status = format!("{filename} - {} lines{modified_indicator}", document.len());
+        if document.is_indexing() {
+            status.push_str(" (indexing)");
+        }
+        if self.buffers.len() > 1 {
+            let buffer = format!(
+                " - buffer {}/{}",
+                buffer.saturating_add(1),
+                self.buffers.len()
+            );
+            status.push_str(&buffer);
+        }
+        if !secondary_cursors.is_empty() {
+            let cursors = format!(" - {} cursors", secondary_cursors.len().saturating_add(1));
+            status.push_str(&cursors);
+        }
+        let line_ending = document
+            .line_ending()
+            .map_or("Mixed", |line_ending| line_ending.name());
         let line_indicator = format!(
-            "{} | {}/{}",
-            self.document.file_type(),
-            self.cursor_position.y.saturating_add(1), /* 1-based */
-            self.document.len()
+            "{} | {} | {line_ending} | {}/{}",
+            document.file_type(),
+            document.encoding(),
+            cursor_position.y.saturating_add(1), /* 1-based */
+            document.len()
         );
         #[allow(clippy::arithmetic_side_effects)]
         let len = status.len() + line_indicator.len();
-        let term_width = self.terminal.size().width as usize;
+        let term_width = area.width;
         status.push_str(&" ".repeat(term_width.saturating_sub(len)));
         // XXX: Isn't status always less than or equal to term_width?
         status.truncate(term_width);
         // The current line number is aligned to the right edge.
         status = format!("{status}{line_indicator}");
+        // Narrow windows cut off the line number rather than spilling over the ones beside them.
+        status = status.chars().take(term_width).collect();
+        Terminal::cursor_position(&Position {
+            x: area.x,
+            y: area.y.saturating_add(area.height),
+        });
         Terminal::set_bg_color(STATUS_BG_COLOR);
-        Terminal::set_fg_color(STATUS_FG_COLOR);
-        println!("{status}\r");
+        Terminal::set_fg_color(if focus {
+            STATUS_FG_COLOR
+        } else {
+            INACTIVE_STATUS_FG_COLOR
+        });
+        print!("{status}");
         Terminal::reset_bg_color();
         Terminal::reset_fg_color();
     }
 
     fn draw_message_bar(&self) {
+        Terminal::cursor_position(&Position {
+            x: 0,
+            y: self.screen().height,
+        });
         Terminal::clear_current_line();
         let message = &self.status_message;
         if message.time.elapsed() < Duration::from_secs(5) {
@@ -375,19 +1914,41 @@ impl Editor {
     }
 
     /// Prompt the user for input. `None` is returned if the user cancels the prompt.
-    /// The callback is called whenever a key is pressed, along with the key and the current input.
+    /// The callback is called whenever a key is pressed, along with the key and the current input,
+    /// which it may change.
     /// # Errors
     /// Returns an error if the user input can't be read.
     /// XXX: Optional callback?
-    fn prompt<C>(&mut self, prompt: &str, mut callback: C) -> Result<Option<String>, Error>
+    fn prompt<C>(&mut self, prompt: &str, callback: C) -> Result<Option<String>, Error>
+    where
+        C: FnMut(&mut Self, Key, &mut String),
+    {
+        self.prompt_labeled(|_, _| prompt.to_owned(), callback)
+    }
+
+    /// Like `prompt`, with the prompt made by `label` from the editor and the input every
+    /// time it's shown, so that it can tell about them.
+    /// # Errors
+    /// Returns an error if the user input can't be read.
+    fn prompt_labeled<L, C>(&mut self, label: L, mut callback: C) -> Result<Option<String>, Error>
     where
-        C: FnMut(&mut Self, Key, &String),
+        L: Fn(&mut Self, &str) -> String,
+        C: FnMut(&mut Self, Key, &mut String),
     {
         let mut result = String::new();
         loop {
+            let prompt = label(self, &result);
             self.status_message = StatusMessage::from(format!("{prompt}{result}"));
             self.refresh_screen()?;
-            let key = Terminal::read_key()?;
+            let key = if self.document.is_searching() {
+                // A search running in the background is polled until it's done, by calling back
+                // with `Key::Null`.
+                self.terminal
+                    .read_key_timeout(SEARCH_POLL_INTERVAL)?
+                    .map_or(Key::Null, |(key, _)| key)
+            } else {
+                self.terminal.read_key()?.0
+            };
             match key {
                 Key::Backspace => {
                     if !result.is_empty() {
@@ -407,7 +1968,7 @@ impl Editor {
                 }
                 _ => (),
             }
-            callback(self, key, &result);
+            callback(self, key, &mut result);
         }
         self.status_message.clear();
         if result.is_empty() {
@@ -417,48 +1978,285 @@ impl Editor {
         }
     }
 
+    /// Like `Document::find_after`, waiting for a large file to be searched in the background,
+    /// which a key press stops.
+    fn find_waiting(&mut self, query: &Query, after: &Position) -> Option<(Position, usize)> {
+        loop {
+            let found = self.document.find_after(query, after);
+            if !self.document.is_searching() {
+                self.status_message.clear();
+                return found;
+            }
+            self.status_message =
+                StatusMessage::from("Searching... (press any key to stop)".to_owned());
+            let key = self
+                .refresh_screen()
+                .and_then(|()| self.terminal.read_key_timeout(SEARCH_POLL_INTERVAL));
+            if !matches!(key, Ok(None)) {
+                self.document.cancel_search();
+                self.status_message.clear();
+                return None;
+            }
+        }
+    }
+
     fn save(&mut self) {
         // If the file has no name, prompt the user for one.
         if self.document.filename.is_none() {
-            let new_name = self.prompt("Save as: ", |_, _, _| {}).unwrap_or(None);
-            if new_name.is_none() {
-                self.status_message = StatusMessage::from("Save aborted.".to_owned());
+            self.save_as();
+            return;
+        }
+        if self.document.is_read_only() {
+            self.status_message = StatusMessage::from(
+                "The buffer is read-only. Press Alt-O to allow saving.".to_owned(),
+            );
+            return;
+        }
+        if self.document.is_changed_on_disk()
+            && !self.resolve_external_change(true).unwrap_or(false)
+        {
+            return;
+        }
+        let msg = match self.document.save() {
+            Ok(()) => "File saved sucessfully.".to_owned(),
+            Err(error) => format!("ERR: Could not save file: {error}"),
+        };
+        self.status_message = StatusMessage::from(msg);
+    }
+
+    /// Saves the document under a new name, which it keeps from then on.
+    fn save_as(&mut self) {
+        let Some(filename) = self.prompt_target("Save as (Tab to complete): ") else {
+            self.status_message = StatusMessage::from("Save aborted.".to_owned());
+            return;
+        };
+        if self
+            .document
+            .filename
+            .as_ref()
+            .is_some_and(|own| is_same_file(own, &filename))
+        {
+            self.save();
+            return;
+        }
+        let msg = match self.document.save_as(&filename) {
+            Ok(()) => "File saved sucessfully.".to_owned(),
+            Err(error) => format!("ERR: Could not save file: {error}"),
+        };
+        self.status_message = StatusMessage::from(msg);
+    }
+
+    /// Writes a copy of the document elsewhere, leaving its name as it is.
+    fn write_copy(&mut self) {
+        let Some(filename) = self.prompt_target("Write a copy to (Tab to complete): ") else {
+            self.status_message = StatusMessage::from("Write aborted.".to_owned());
+            return;
+        };
+        if self
+            .document
+            .filename
+            .as_ref()
+            .is_some_and(|own| is_same_file(own, &filename))
+        {
+            self.save();
+            return;
+        }
+        let msg = match self.document.write_copy(&filename) {
+            Ok(()) => format!("Wrote a copy to {filename}."),
+            Err(error) => format!("ERR: Could not write file: {error}"),
+        };
+        self.status_message = StatusMessage::from(msg);
+    }
+
+    /// Replaces the matches of a query from the cursor on, asking about each of them.
+    fn replace(&mut self) {
+        self.collapse_cursors();
+        let Ok(Some(text)) = self.prompt_labeled(
+            |editor, input| format!("Replace{}: ", editor.search_note(input)),
+            |editor, key, _| editor.toggle_search_option(key),
+        ) else {
+            self.status_message = StatusMessage::from("Replace canceled.".to_owned());
+            return;
+        };
+        let query = match self.compile_query(&text) {
+            Ok(query) => query,
+            Err(error) => {
+                self.status_message = StatusMessage::from(format!(
+                    "Invalid pattern: {}",
+                    search::describe_error(&error)
+                ));
+                return;
+            }
+        };
+        // An empty replacement deletes the matches, so only Esc cancels.
+        let mut canceled = false;
+        let replacement = self.prompt(&format!("Replace {text} with: "), |_, key, _| {
+            canceled |= key == Key::Esc;
+        });
+        let replacement = match replacement {
+            Ok(replacement) if !canceled => replacement.unwrap_or_default(),
+            _ => {
+                self.status_message = StatusMessage::from("Replace canceled.".to_owned());
                 return;
             }
-            self.document.filename = new_name;
-        }
-        let msg = if self.document.save().is_ok() {
-            "File saved sucessfully."
-        } else {
-            "Error writing file!"
         };
-        self.status_message = StatusMessage::from(msg.to_owned());
+        let replaced = self.replace_matches(&query, &replacement);
+        self.document.highlight_restore();
+        self.scroll();
+        self.status_message = StatusMessage::from(match replaced {
+            Ok(count) => format!("Replacements made: {count}."),
+            Err((count, error)) => format!("Replacements made: {count}. ERR: {error}"),
+        });
+    }
+
+    /// Steps through the matches of `query`, highlighting each and asking whether to replace it.
+    /// Returns the number of replacements, which is also returned along with an error, since the
+    /// replacements made until then stay.
+    fn replace_matches(
+        &mut self,
+        query: &Query,
+        replacement: &str,
+    ) -> Result<usize, (usize, Error)> {
+        let mut count = 0usize;
+        let mut at = self.cursor_position.clone();
+        while let Some((found, len)) = self.document.find_after(query, &at) {
+            self.cursor_position = found.clone();
+            self.scroll();
+            self.document.highlight_restore();
+            self.document.highlight_match(&found, len);
+            self.status_message =
+                StatusMessage::from("Replace this match? (Y)es, (N)o, (A)ll or (Q)uit".to_owned());
+            let key = self
+                .refresh_screen()
+                .and_then(|()| self.terminal.read_key())
+                .map_err(|error| (count, error))?;
+            match key.0 {
+                Key::Char('y' | 'Y') => {
+                    at = self.replace_match(query, &found, len, replacement);
+                    count = count.saturating_add(1);
+                }
+                Key::Char('n' | 'N') => {
+                    at = Position {
+                        x: found.x.saturating_add(1),
+                        y: found.y,
+                    };
+                }
+                Key::Char('a' | 'A') => {
+                    // The rest of the matches are undone as a whole.
+                    self.document.begin_change();
+                    at = found;
+                    while let Some((found, len)) = self.document.find_after(query, &at) {
+                        at = self.replace_match(query, &found, len, replacement);
+                        count = count.saturating_add(1);
+                    }
+                    self.document.end_change();
+                    self.cursor_position = at;
+                    break;
+                }
+                Key::Char('q' | 'Q') | Key::Esc => break,
+                _ => (),
+            }
+        }
+        Ok(count)
+    }
+
+    /// Replaces the `len` graphemes of a match at `at`, with capture groups expanded. Returns
+    /// where to search for the next match.
+    fn replace_match(
+        &mut self,
+        query: &Query,
+        at: &Position,
+        len: usize,
+        replacement: &str,
+    ) -> Position {
+        let expanded = self
+            .document
+            .row(at.y)
+            .map(|row| row.expand(query, at.x, replacement))
+            .unwrap_or_default();
+        let mut next = self.document.replace(at, len, &expanded);
+        // An empty match would be found again right where it was replaced.
+        if len == 0 {
+            next.x = next.x.saturating_add(1);
+        }
+        next
+    }
+
+    /// Toggles a search option by its key in a search prompt.
+    fn toggle_search_option(&mut self, key: Key) {
+        let options = &mut self.search_options;
+        match key {
+            Key::Alt('r') => options.regex = !options.regex,
+            Key::Alt('c') => options.case = options.case.next(),
+            Key::Alt('w') => options.whole_word = !options.whole_word,
+            _ => (),
+        }
+    }
+
+    /// Compiles the input of a prompt with the current search options, unless it was compiled
+    /// last already.
+    fn compile_query(&mut self, input: &str) -> Result<Query, regex::Error> {
+        if let Some((text, options, compiled)) = &self.compiled_query {
+            if text == input && *options == self.search_options {
+                return compiled.clone();
+            }
+        }
+        let compiled = Query::new(input, self.search_options);
+        self.compiled_query = Some((input.to_owned(), self.search_options, compiled.clone()));
+        compiled
+    }
+
+    /// The search options that are on, along with what's wrong with the `input` if it isn't a
+    /// valid pattern, as told in a search prompt.
+    fn search_note(&mut self, input: &str) -> String {
+        match self.compile_query(input) {
+            Err(error) if !input.is_empty() => format!(
+                "{} ({})",
+                self.search_options,
+                search::describe_error(&error)
+            ),
+            _ if self.document.is_searching() => format!("{} (searching...)", self.search_options),
+            _ => self.search_options.to_string(),
+        }
     }
 
     /// Searches for a query in the document with incremental backward and forward search.
     fn search(&mut self) {
+        self.collapse_cursors();
         let old_position = self.cursor_position.clone();
         // We start by searching forward.
         let mut forward = true;
+        // Whether the cursor was moved ahead to find the next match, and has to be moved back if
+        // there's none. For a large file, that's only known once it has been searched.
+        let mut stepped = false;
         // NOTE: Every time the query is updated, either by typing or deleting,
         // the cursor is moved back to the old position to start a new forward search.
-        let incremental_search = |editor: &mut Self, key: Key, partial_query: &String| {
-            let mut moved = false;
+        let incremental_search = |editor: &mut Self, key: Key, partial_query: &mut String| {
+            editor.toggle_search_option(key);
             match key {
-                Key::Char(_) | Key::Backspace => {
+                Key::Char(_) | Key::Backspace | Key::Alt('r' | 'c' | 'w') => {
                     editor.cursor_position = old_position.clone();
                     editor.scroll();
                     forward = true;
+                    stepped = false;
                 }
                 Key::Right | Key::Down => {
                     // NOTE: For our incremental search to not return the current position.
                     // Will move back if no next match is found.
+                    if stepped {
+                        // The last step is still being searched from.
+                        editor.move_cursor(Key::Left);
+                    }
                     editor.move_cursor(Key::Right);
                     forward = true;
-                    moved = true;
+                    stepped = true;
                 }
                 Key::Left | Key::Up => {
                     // The current position is excluded from the search, so will not return the current position.
+                    if stepped {
+                        editor.move_cursor(Key::Left);
+                        stepped = false;
+                    }
                     forward = false;
                 }
                 _ => (),
@@ -470,23 +2268,34 @@ impl Editor {
             };
 
             editor.document.highlight_restore();
+            // An invalid pattern is told about in the prompt until it's fixed.
+            let Ok(query) = editor.compile_query(partial_query) else {
+                return;
+            };
 
-            if let Some(position) =
-                find_func(&editor.document, partial_query, &editor.cursor_position)
+            if let Some((position, _)) =
+                find_func(&editor.document, &query, &editor.cursor_position)
             {
                 editor.cursor_position = position;
                 editor.scroll();
-            } else if moved {
+                stepped = false;
+            } else if stepped && !editor.document.is_searching() {
                 // Not found, move the offset back.
                 editor.move_cursor(Key::Left);
+                stepped = false;
             }
-            editor.document.highlight_query(partial_query);
+            editor.document.highlight_query(&query);
         };
 
         // Perform the search.
-        if let Some(query) = self
-            .prompt(
-                "Search (ESC to cancel, Arrows to navigate): ",
+        if let Some(text) = self
+            .prompt_labeled(
+                |editor, input| {
+                    format!(
+                        "Search{} (ESC to cancel, Arrows to navigate, Alt-R/C/W: regex/case/word): ",
+                        editor.search_note(input)
+                    )
+                },
                 incremental_search,
             )
             .unwrap_or(None)
@@ -496,13 +2305,30 @@ impl Editor {
             // Otherwise, the cursor is moved back to the old position.
             // However, there's a chance that we're at the old position if it's the first match.
             // So we perform an additional forward search.
-            if self.cursor_position == old_position
-                && self
-                    .document
-                    .find_after(&query, &self.cursor_position)
-                    .is_none()
-            {
-                self.status_message = StatusMessage::from(format!("Not found: {query}"));
+            match self.compile_query(&text) {
+                Err(error) => {
+                    self.status_message = StatusMessage::from(format!(
+                        "Invalid pattern: {}",
+                        search::describe_error(&error)
+                    ));
+                    self.cursor_position = old_position;
+                    self.scroll();
+                }
+                // The next match of a large file may also still be being searched for.
+                Ok(query) if stepped || self.cursor_position == old_position => {
+                    let from = self.cursor_position.clone();
+                    match self.find_waiting(&query, &from) {
+                        Some((position, _)) => {
+                            self.cursor_position = position;
+                            self.scroll();
+                        }
+                        None if stepped => self.move_cursor(Key::Left),
+                        None => {
+                            self.status_message = StatusMessage::from(format!("Not found: {text}"));
+                        }
+                    }
+                }
+                Ok(_) => (),
             }
         } else {
             self.status_message = StatusMessage::from("Search canceled.".to_owned());
@@ -510,11 +2336,46 @@ impl Editor {
             self.cursor_position = old_position;
             self.scroll();
         }
+        self.document.cancel_search();
         self.document.highlight_restore();
     }
 }
 
+/// The name of a buffer as shown to the user.
+fn buffer_name(document: &Document) -> String {
+    document
+        .filename
+        .clone()
+        .unwrap_or_else(|| "[No Name]".to_owned())
+}
+
+/// Whether two paths name the same file, however they are written. Paths of files that don't
+/// exist yet are compared as they are.
+fn is_same_file(a: &str, b: &str) -> bool {
+    match (fs::canonicalize(a), fs::canonicalize(b)) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => a == b,
+    }
+}
+
 fn die(e: &Error) {
     Terminal::clear_screen();
     panic!("{}", e);
 }
+
+/// The selection between `anchor` and `cursor`, ordered from its start to its end. `None` if
+/// they are the same.
+fn ordered_selection(anchor: &Position, cursor: &Position) -> Option<(Position, Position)> {
+    match (anchor.y, anchor.x).cmp(&(cursor.y, cursor.x)) {
+        Ordering::Less => Some((anchor.clone(), cursor.clone())),
+        Ordering::Greater => Some((cursor.clone(), anchor.clone())),
+        Ordering::Equal => None,
+    }
+}
+
+/// The graphemes at which the screen lines of the row at `y` start when wrapped at `width`.
+fn wrap_points(document: &Document, y: usize, width: usize, tab_stop: usize) -> Vec<usize> {
+    document
+        .row(y)
+        .map_or_else(|| vec![0], |row| row.wrap_points(width, tab_stop))
+}
@@ -0,0 +1,120 @@
+use encoding_rs::{Encoding, UTF_16BE, UTF_16LE, UTF_8, WINDOWS_1252};
+use std::io::Error;
+
+/// The encoding of files that are neither valid UTF-8 nor start with a byte order mark. Being
+/// a superset of Latin-1, it decodes most legacy Western text correctly.
+const FALLBACK_ENCODING: &Encoding = WINDOWS_1252;
+
+/// The character encoding of a file, along with whether it starts with a byte order mark.
+#[derive(Copy, Clone)]
+pub struct FileEncoding {
+    encoding: &'static Encoding,
+    has_bom: bool,
+}
+
+impl Default for FileEncoding {
+    fn default() -> Self {
+        Self {
+            encoding: UTF_8,
+            has_bom: false,
+        }
+    }
+}
+
+impl FileEncoding {
+    /// The encoding named by `label`, such as `utf-16le` or `latin1`. A byte order mark is only
+    /// written for UTF-16, which can't be told apart from other encodings without one.
+    #[must_use]
+    pub fn for_label(label: &str) -> Option<Self> {
+        let encoding = Encoding::for_label(label.trim().as_bytes())?;
+        Some(Self {
+            encoding,
+            has_bom: encoding == UTF_16LE || encoding == UTF_16BE,
+        })
+    }
+
+    #[must_use]
+    pub fn name(&self) -> &'static str {
+        self.encoding.name()
+    }
+
+    /// Decodes the content of a file by its byte order mark if it has one, as UTF-8 if it is
+    /// valid, and in the fallback encoding otherwise.
+    #[must_use]
+    pub fn detect(bytes: &[u8]) -> (String, Self) {
+        if let Some((encoding, bom_len)) = Encoding::for_bom(bytes) {
+            let content = bytes.get(bom_len..).unwrap_or_default();
+            let (text, _) = encoding.decode_without_bom_handling(content);
+            let file_encoding = Self {
+                encoding,
+                has_bom: true,
+            };
+            return (text.into_owned(), file_encoding);
+        }
+        if let Ok(text) = String::from_utf8(bytes.to_vec()) {
+            return (text, Self::default());
+        }
+        let (text, _) = FALLBACK_ENCODING.decode_without_bom_handling(bytes);
+        let file_encoding = Self {
+            encoding: FALLBACK_ENCODING,
+            has_bom: false,
+        };
+        (text.into_owned(), file_encoding)
+    }
+
+    /// Decodes the content of a file in this encoding, skipping its byte order mark if it has
+    /// one. Malformed sequences are replaced by U+FFFD.
+    #[must_use]
+    pub fn decode(self, bytes: &[u8]) -> (String, Self) {
+        let (text, has_bom) = match Encoding::for_bom(bytes) {
+            Some((encoding, bom_len)) if encoding == self.encoding => (
+                encoding.decode_without_bom_handling(bytes.get(bom_len..).unwrap_or_default()),
+                true,
+            ),
+            _ => (self.encoding.decode_without_bom_handling(bytes), false),
+        };
+        (
+            text.0.into_owned(),
+            Self {
+                encoding: self.encoding,
+                has_bom,
+            },
+        )
+    }
+
+    /// # Errors
+    /// Returns an error if the text contains characters that the encoding can't represent.
+    pub fn encode(&self, text: &str) -> Result<Vec<u8>, Error> {
+        let mut bytes = Vec::new();
+        // `encoding_rs` only decodes UTF-16, as the web never sends it.
+        if self.encoding == UTF_16LE || self.encoding == UTF_16BE {
+            let is_le = self.encoding == UTF_16LE;
+            let units = self
+                .has_bom
+                .then_some(0xFEFF)
+                .into_iter()
+                .chain(text.encode_utf16());
+            #[allow(clippy::little_endian_bytes, clippy::big_endian_bytes)]
+            for unit in units {
+                bytes.extend(if is_le {
+                    unit.to_le_bytes()
+                } else {
+                    unit.to_be_bytes()
+                });
+            }
+            return Ok(bytes);
+        }
+        if self.has_bom && self.encoding == UTF_8 {
+            bytes.extend(b"\xEF\xBB\xBF");
+        }
+        let (encoded, _, had_unmappable) = self.encoding.encode(text);
+        if had_unmappable {
+            return Err(Error::other(format!(
+                "The text contains characters that {} can't represent",
+                self.name()
+            )));
+        }
+        bytes.extend(encoded.iter());
+        Ok(bytes)
+    }
+}
@@ -4,6 +4,12 @@ use std::path::Path;
 pub struct FileType {
     name: String,
     hl_opts: HighlightingOptions,
+    /// One level of indentation, added after an opening bracket. `None` if the filetype doesn't
+    /// indent by brackets.
+    indent_unit: Option<String>,
+    /// The opening and closing characters that are typed in pairs. Quotes are left out where they
+    /// also have other uses, like the `'` of Rust lifetimes.
+    auto_pairs: Vec<(char, char)>,
 }
 
 impl Default for FileType {
@@ -11,6 +17,8 @@ impl Default for FileType {
         Self {
             name: String::from("No filetype"),
             hl_opts: HighlightingOptions::default(),
+            indent_unit: None,
+            auto_pairs: Vec::new(),
         }
     }
 }
@@ -26,6 +34,16 @@ impl FileType {
         &self.hl_opts
     }
 
+    #[must_use]
+    pub fn indent_unit(&self) -> Option<&str> {
+        self.indent_unit.as_deref()
+    }
+
+    #[must_use]
+    pub fn auto_pairs(&self) -> &[(char, char)] {
+        &self.auto_pairs
+    }
+
     #[must_use]
     pub fn from(filename: &str) -> Self {
         let filename = Path::new(filename);
@@ -126,6 +144,8 @@ impl FileType {
                         '>',
                     ],
                 },
+                indent_unit: Some("    ".to_owned()),
+                auto_pairs: vec![('(', ')'), ('[', ']'), ('{', '}'), ('"', '"')],
             };
         }
         Self::default()
@@ -20,6 +20,8 @@ pub enum Type {
     Number,
     // Search results.
     Search,
+    // Selected text; rendered inverted rather than in a color of its own.
+    Selection,
     String,
     Character,
     Comment,
@@ -40,7 +42,7 @@ impl Type {
             Type::Keyword => &color::Magenta,
             Type::DataType => &color::LightMagenta,
             Type::Punctuation => &color::Cyan,
-            Type::None => &color::Reset,
+            Type::None | Type::Selection => &color::Reset,
         }
     }
 }
@@ -0,0 +1,406 @@
+use crate::Position;
+use unicode_segmentation::UnicodeSegmentation;
+
+/// A primitive edit of a document. The text of an insertion is either a single grapheme or a row
+/// break (`"\n"`); a deletion may take any range of text, with rows separated by `"\n"`.
+#[derive(Clone)]
+pub enum Edit {
+    /// `new_row` is set if the insertion appended a row to the end of the document, which has to
+    /// be removed again when undoing it.
+    Insert {
+        at: Position,
+        text: String,
+        new_row: bool,
+    },
+    Delete {
+        at: Position,
+        text: String,
+    },
+    /// Swaps the row `at` starts with the one below it. The rows are moved as they are, keeping
+    /// their row breaks.
+    SwapRows {
+        at: Position,
+    },
+}
+
+impl Edit {
+    fn at(&self) -> &Position {
+        match self {
+            Edit::Insert { at, .. } | Edit::Delete { at, .. } | Edit::SwapRows { at } => at,
+        }
+    }
+
+    fn text(&self) -> &str {
+        match self {
+            Edit::Insert { text, .. } | Edit::Delete { text, .. } => text,
+            Edit::SwapRows { .. } => "",
+        }
+    }
+
+    /// Where the cursor ends up after performing the edit.
+    #[must_use]
+    pub fn end(&self) -> Position {
+        match self {
+            Edit::Insert { .. } => self.text_end(),
+            Edit::Delete { at, .. } | Edit::SwapRows { at } => at.clone(),
+        }
+    }
+
+    /// Where the text ends, as inserted or before it was deleted.
+    #[must_use]
+    pub fn text_end(&self) -> Position {
+        let (at, text) = (self.at(), self.text());
+        let rows = text.matches('\n').count();
+        let last_len = text
+            .rsplit('\n')
+            .next()
+            .unwrap_or_default()
+            .graphemes(true)
+            .count();
+        if rows == 0 {
+            Position {
+                x: at.x.saturating_add(last_len),
+                y: at.y,
+            }
+        } else {
+            Position {
+                x: last_len,
+                y: at.y.saturating_add(rows),
+            }
+        }
+    }
+
+    /// The edit that reverts this one.
+    #[must_use]
+    pub fn inverse(&self) -> Self {
+        match self {
+            Edit::Insert { at, text, .. } => Edit::Delete {
+                at: at.clone(),
+                text: text.clone(),
+            },
+            Edit::Delete { at, text } => Edit::Insert {
+                at: at.clone(),
+                text: text.clone(),
+                new_row: false,
+            },
+            Edit::SwapRows { .. } => self.clone(),
+        }
+    }
+
+    /// Where `position` ends up after the edit, so that it stays at the same text. A position
+    /// within deleted text goes to where the deletion starts.
+    #[must_use]
+    pub fn shift(&self, position: &Position) -> Position {
+        let at = self.at();
+        if (position.y, position.x) < (at.y, at.x) {
+            return position.clone();
+        }
+        let end = self.text_end();
+        let rows = end.y.saturating_sub(at.y);
+        match self {
+            Edit::Insert { .. } if position.y == at.y => Position {
+                x: end.x.saturating_add(position.x.saturating_sub(at.x)),
+                y: end.y,
+            },
+            Edit::Insert { .. } => Position {
+                x: position.x,
+                y: position.y.saturating_add(rows),
+            },
+            Edit::Delete { .. } if (position.y, position.x) < (end.y, end.x) => at.clone(),
+            Edit::Delete { .. } if position.y == end.y => Position {
+                x: at.x.saturating_add(position.x.saturating_sub(end.x)),
+                y: at.y,
+            },
+            Edit::Delete { .. } => Position {
+                x: position.x,
+                y: position.y.saturating_sub(rows),
+            },
+            Edit::SwapRows { .. } if position.y == at.y => Position {
+                x: position.x,
+                y: at.y.saturating_add(1),
+            },
+            Edit::SwapRows { .. } if position.y == at.y.saturating_add(1) => Position {
+                x: position.x,
+                y: at.y,
+            },
+            Edit::SwapRows { .. } => position.clone(),
+        }
+    }
+
+    /// Whether `next` continues this edit closely enough to be undone together with it.
+    /// Edits are grouped word by word: a whitespace following a non-whitespace starts a new group,
+    /// and a row break always does.
+    fn is_continued_by(&self, next: &Self) -> bool {
+        if self.text() == "\n" || next.text() == "\n" {
+            return false;
+        }
+        let is_space = |text: &str| text.chars().all(char::is_whitespace);
+        if is_space(next.text()) && !is_space(self.text()) {
+            return false;
+        }
+        match (self, next) {
+            (Edit::Insert { .. }, Edit::Insert { at, .. }) => *at == self.end(),
+            // Either the Delete key (same position) or Backspace (one to the left).
+            (Edit::Delete { at: prev, .. }, Edit::Delete { at, .. }) => {
+                at.y == prev.y && (at.x == prev.x || at.x.saturating_add(1) == prev.x)
+            }
+            _ => false,
+        }
+    }
+}
+
+/// A group of edits that is undone and redone as a whole.
+struct Change {
+    edits: Vec<Edit>,
+    /// Identifies the change, so that the saved state can be recognized after undo and redo.
+    id: usize,
+    /// Whether later edits may still be coalesced into this change.
+    is_open: bool,
+}
+
+impl Change {
+    /// Where the change starts, which is where the cursor is placed after undoing it.
+    fn start(&self) -> Position {
+        self.edits
+            .iter()
+            .map(Edit::at)
+            .min_by_key(|at| (at.y, at.x))
+            .cloned()
+            .unwrap_or_default()
+    }
+}
+
+/// The undo and redo stacks of a document.
+#[derive(Default)]
+pub struct History {
+    undo_stack: Vec<Change>,
+    redo_stack: Vec<Change>,
+    /// The id of the last change; ids are never reused. 0 stands for the unmodified document.
+    last_id: usize,
+    /// The id of the change on top of the undo stack when the document was last saved.
+    saved_id: usize,
+    /// The nesting depth of `begin_change`. While positive, every edit goes into the same change.
+    group_depth: usize,
+    /// Whether the next edit in a group has to start a new change.
+    group_pending: bool,
+}
+
+impl History {
+    fn current_id(&self) -> usize {
+        self.undo_stack.last().map_or(0, |change| change.id)
+    }
+
+    /// Whether undoing and redoing brought the document back to its saved state.
+    #[must_use]
+    pub fn is_at_saved(&self) -> bool {
+        self.current_id() == self.saved_id
+    }
+
+    /// Also seals the last change, so that later edits aren't merged into the saved state.
+    pub fn mark_saved(&mut self) {
+        self.seal();
+        self.saved_id = self.current_id();
+    }
+
+    /// Makes the current state differ from the saved one for good, as after changes made outside
+    /// the history, so that no undo or redo can bring the document back to its saved state.
+    pub fn mark_unsaved(&mut self) {
+        self.seal();
+        // An id that no change will ever have.
+        self.last_id = self.last_id.saturating_add(1);
+        self.saved_id = self.last_id;
+    }
+
+    /// Groups all edits until the matching `end_change` into a single change.
+    pub fn begin_change(&mut self) {
+        if self.group_depth == 0 {
+            self.group_pending = true;
+        }
+        self.group_depth = self.group_depth.saturating_add(1);
+    }
+
+    pub fn end_change(&mut self) {
+        self.group_depth = self.group_depth.saturating_sub(1);
+        if self.group_depth == 0 {
+            self.group_pending = false;
+            self.seal();
+        }
+    }
+
+    /// Prevents later edits from being coalesced into the last change.
+    pub fn seal(&mut self) {
+        if let Some(change) = self.undo_stack.last_mut() {
+            change.is_open = false;
+        }
+    }
+
+    pub fn record(&mut self, edit: Edit) {
+        // A new edit makes the undone changes unreachable.
+        self.redo_stack.clear();
+        if self.group_depth > 0 && !self.group_pending {
+            if let Some(change) = self.undo_stack.last_mut() {
+                change.edits.push(edit);
+                return;
+            }
+        }
+        if self.group_depth == 0 {
+            if let Some(change) = self.undo_stack.last_mut() {
+                if change.is_open
+                    && change
+                        .edits
+                        .last()
+                        .is_some_and(|last| last.is_continued_by(&edit))
+                {
+                    change.edits.push(edit);
+                    return;
+                }
+            }
+        }
+        self.group_pending = false;
+        self.seal();
+        self.last_id = self.last_id.saturating_add(1);
+        self.undo_stack.push(Change {
+            edits: vec![edit],
+            id: self.last_id,
+            is_open: true,
+        });
+    }
+
+    /// Takes the last change off the undo stack. Returns its edits, which have to be reverted in
+    /// reverse order, along with where the cursor goes afterwards.
+    pub fn undo(&mut self) -> Option<(Vec<Edit>, Position)> {
+        let mut change = self.undo_stack.pop()?;
+        change.is_open = false;
+        let edits = change.edits.clone();
+        let cursor = change.start();
+        self.redo_stack.push(change);
+        Some((edits, cursor))
+    }
+
+    /// Takes the last undone change off the redo stack. Returns its edits, which have to be
+    /// performed in order, along with where the cursor goes afterwards.
+    pub fn redo(&mut self) -> Option<(Vec<Edit>, Position)> {
+        let change = self.redo_stack.pop()?;
+        let edits = change.edits.clone();
+        let cursor = change.edits.last().map(Edit::end).unwrap_or_default();
+        self.undo_stack.push(change);
+        Some((edits, cursor))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn insert(x: usize, text: &str) -> Edit {
+        Edit::Insert {
+            at: Position { x, y: 0 },
+            text: text.to_owned(),
+            new_row: false,
+        }
+    }
+
+    /// Records typing `text` on the first row from `x` on, a grapheme at a time.
+    fn type_text(history: &mut History, x: usize, text: &str) {
+        for (i, grapheme) in text.graphemes(true).enumerate() {
+            history.record(insert(x.saturating_add(i), grapheme));
+        }
+    }
+
+    fn undone_text(history: &mut History) -> Option<String> {
+        let (edits, _) = history.undo()?;
+        Some(edits.iter().map(Edit::text).collect())
+    }
+
+    #[test]
+    fn merges_consecutive_edits_word_by_word() {
+        let mut history = History::default();
+        type_text(&mut history, 0, "ab cd");
+        assert_eq!(undone_text(&mut history).as_deref(), Some(" cd"));
+        assert_eq!(undone_text(&mut history).as_deref(), Some("ab"));
+        assert!(history.undo().is_none());
+    }
+
+    #[test]
+    fn does_not_merge_edits_elsewhere() {
+        let mut history = History::default();
+        history.record(insert(0, "a"));
+        history.record(insert(5, "b"));
+        assert_eq!(undone_text(&mut history).as_deref(), Some("b"));
+        assert_eq!(undone_text(&mut history).as_deref(), Some("a"));
+    }
+
+    #[test]
+    fn groups_nested_changes_into_one() {
+        let mut history = History::default();
+        type_text(&mut history, 0, "x");
+        history.begin_change();
+        history.record(insert(1, "\n"));
+        history.begin_change();
+        type_text(&mut history, 0, "a b");
+        history.end_change();
+        history.record(insert(9, "c"));
+        history.end_change();
+        type_text(&mut history, 10, "d");
+        assert_eq!(undone_text(&mut history).as_deref(), Some("d"));
+        assert_eq!(undone_text(&mut history).as_deref(), Some("\na bc"));
+        assert_eq!(undone_text(&mut history).as_deref(), Some("x"));
+    }
+
+    #[test]
+    fn keeps_the_saved_state_across_undo_and_redo() {
+        let mut history = History::default();
+        assert!(history.is_at_saved());
+        type_text(&mut history, 0, "abc");
+        assert!(!history.is_at_saved());
+        history.mark_saved();
+        assert!(history.is_at_saved());
+        history.undo();
+        assert!(!history.is_at_saved());
+        history.redo();
+        assert!(history.is_at_saved());
+    }
+
+    #[test]
+    fn does_not_merge_edits_into_the_saved_change() {
+        let mut history = History::default();
+        type_text(&mut history, 0, "abc");
+        history.mark_saved();
+        type_text(&mut history, 3, "d");
+        assert!(!history.is_at_saved());
+        assert_eq!(undone_text(&mut history).as_deref(), Some("d"));
+        assert!(history.is_at_saved());
+        history.redo();
+        assert!(!history.is_at_saved());
+    }
+
+    #[test]
+    fn shifts_positions_after_edits() {
+        let at = |x, y| Position { x, y };
+        let shifted = |edit: &Edit, x, y| {
+            let Position { x, y } = edit.shift(&at(x, y));
+            (x, y)
+        };
+        let inserted = Edit::Insert {
+            at: at(2, 1),
+            text: "ab\ncde".to_owned(),
+            new_row: false,
+        };
+        assert_eq!(shifted(&inserted, 1, 1), (1, 1));
+        assert_eq!(shifted(&inserted, 2, 1), (3, 2));
+        assert_eq!(shifted(&inserted, 5, 1), (6, 2));
+        assert_eq!(shifted(&inserted, 0, 3), (0, 4));
+        let deleted = inserted.inverse();
+        assert_eq!(shifted(&deleted, 1, 1), (1, 1));
+        assert_eq!(shifted(&deleted, 1, 2), (2, 1));
+        assert_eq!(shifted(&deleted, 6, 2), (5, 1));
+        assert_eq!(shifted(&deleted, 0, 4), (0, 3));
+        let swapped = Edit::SwapRows { at: at(0, 1) };
+        assert_eq!(shifted(&swapped, 2, 1), (2, 2));
+        assert_eq!(shifted(&swapped, 3, 2), (3, 1));
+        assert_eq!(shifted(&swapped, 1, 3), (1, 3));
+        let typed = insert(1, "x");
+        assert_eq!(shifted(&typed, 3, 0), (4, 0));
+        assert_eq!(shifted(&typed, 3, 1), (3, 1));
+    }
+}
@@ -0,0 +1,334 @@
+use crate::search::Query;
+use crate::Position;
+use crate::Row;
+use alloc::sync::Arc;
+use core::cell::RefCell;
+use core::ops::Range;
+use core::sync::atomic::{AtomicBool, Ordering};
+use std::fs::File;
+use std::io::{BufRead, BufReader, Error, Read, Seek, SeekFrom};
+use std::sync::Mutex;
+use std::thread;
+
+/// Every how many lines the start of a line is kept in the index. Reading a line means reading
+/// through up to this many lines before it.
+const LINES_PER_CHECKPOINT: usize = 1024;
+/// How much of the file the indexing thread reads at a time.
+const CHUNK_SIZE: usize = 1 << 20;
+
+/// What is known about where the lines of the file start, which grows as the indexing thread
+/// reads on.
+#[derive(Default)]
+struct LineIndex {
+    /// The byte offset of every `LINES_PER_CHECKPOINT`th line, starting with the first one.
+    checkpoints: Vec<u64>,
+    /// The number of lines found so far.
+    lines: usize,
+    is_done: bool,
+}
+
+/// Where the lines of the file are read from, which the search thread reads from as well.
+#[derive(Clone)]
+struct Lines {
+    filename: String,
+    index: Arc<Mutex<LineIndex>>,
+}
+
+/// A match of a query, where it starts along with its length in graphemes.
+type Found = Option<(Position, usize)>;
+
+/// A search that reads through the file in the background, so that the editor isn't held up
+/// until it's done.
+struct Search {
+    query: Query,
+    from: Position,
+    forward: bool,
+    is_canceled: Arc<AtomicBool>,
+    /// `None` while the search is still running.
+    result: Arc<Mutex<Option<Found>>>,
+}
+
+impl Search {
+    fn is_done(&self) -> bool {
+        self.result.lock().map_or(true, |result| result.is_some())
+    }
+
+    fn cancel(&self) {
+        self.is_canceled.store(true, Ordering::Relaxed);
+    }
+}
+
+/// A file too large to be read at once, whose lines are read from the disk as they are shown.
+/// The lines are decoded as UTF-8, with invalid bytes replaced.
+pub struct LargeFile {
+    lines: Lines,
+    /// The rows last asked to be loaded, which are loaded already.
+    requested: Range<usize>,
+    first_loaded: usize,
+    loaded: Vec<Row>,
+    /// The last search, which is kept until its result has been asked for again.
+    search: RefCell<Option<Search>>,
+}
+
+impl Drop for LargeFile {
+    fn drop(&mut self) {
+        self.cancel_search();
+    }
+}
+
+impl LargeFile {
+    /// Opens the file and starts indexing its lines in the background.
+    ///
+    /// # Errors
+    /// Returns an error if the file can't be opened.
+    pub fn open(filename: &str) -> Result<Self, Error> {
+        let file = File::open(filename)?;
+        let index = Arc::new(Mutex::new(LineIndex {
+            checkpoints: vec![0],
+            ..LineIndex::default()
+        }));
+        let shared = Arc::clone(&index);
+        thread::spawn(move || Self::build_index(file, &shared));
+        Ok(Self {
+            lines: Lines {
+                filename: filename.to_owned(),
+                index,
+            },
+            requested: 0..0,
+            first_loaded: 0,
+            loaded: Vec::new(),
+            search: RefCell::new(None),
+        })
+    }
+
+    /// Reads through the file, recording where the lines start. The index is updated once per
+    /// chunk, so that it's not locked for long.
+    #[allow(clippy::arithmetic_side_effects)] // An offset within the file fits in a `u64`.
+    fn build_index(mut file: File, index: &Mutex<LineIndex>) {
+        let mut buffer = vec![0; CHUNK_SIZE];
+        let mut offset = 0u64;
+        let mut lines = 0usize;
+        let mut ends_with_newline = true;
+        loop {
+            let read = match file.read(&mut buffer) {
+                Ok(0) | Err(_) => break,
+                Ok(read) => read,
+            };
+            let chunk = buffer.get(..read).unwrap_or_default();
+            let mut checkpoints = Vec::new();
+            for (at, byte) in (0u64..).zip(chunk) {
+                if *byte == b'\n' {
+                    lines += 1;
+                    if lines.is_multiple_of(LINES_PER_CHECKPOINT) {
+                        checkpoints.push(offset + at + 1);
+                    }
+                }
+            }
+            ends_with_newline = chunk.last() == Some(&b'\n');
+            offset += read as u64;
+            let Ok(mut index) = index.lock() else {
+                return;
+            };
+            index.checkpoints.extend(checkpoints);
+            index.lines = lines;
+        }
+        if let Ok(mut index) = index.lock() {
+            // The last line counts even without a row break.
+            if !ends_with_newline {
+                index.lines = lines.saturating_add(1);
+            }
+            index.is_done = true;
+        }
+    }
+
+    /// The number of lines indexed so far.
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.lines.index.lock().map_or(0, |index| index.lines)
+    }
+
+    /// Whether the whole file has been indexed.
+    #[must_use]
+    pub fn is_indexed(&self) -> bool {
+        self.lines.index.lock().is_ok_and(|index| index.is_done)
+    }
+
+    /// Reads the rows in `range` from the disk unless they are loaded already, along with as many
+    /// before and after them, so that moving around nearby doesn't need another read. Returns
+    /// whether rows were read.
+    pub fn load(&mut self, range: Range<usize>) -> bool {
+        if self.requested.start <= range.start && range.end <= self.requested.end {
+            return false;
+        }
+        let margin = range.len();
+        let requested = range.start.saturating_sub(margin)..range.end.saturating_add(margin);
+        let Some(lines) = self.lines.lines_from(requested.start) else {
+            return false;
+        };
+        self.loaded = lines
+            .take(requested.len())
+            .map(|line| Row::from(line.as_str()))
+            .collect();
+        self.first_loaded = requested.start;
+        self.requested = requested;
+        true
+    }
+
+    /// Reads the rows in `range` from the disk, whether they are loaded or not. Fewer rows are
+    /// returned if the file can't be read through to the end of the range.
+    #[must_use]
+    pub fn read_rows(&self, range: Range<usize>) -> Vec<Row> {
+        self.lines
+            .lines_from(range.start)
+            .map_or_else(Vec::new, |lines| {
+                lines
+                    .take(range.len())
+                    .map(|line| Row::from(line.as_str()))
+                    .collect()
+            })
+    }
+
+    #[must_use]
+    pub fn row(&self, index: usize) -> Option<&Row> {
+        self.loaded.get(index.checked_sub(self.first_loaded)?)
+    }
+
+    pub fn row_mut(&mut self, index: usize) -> Option<&mut Row> {
+        self.loaded.get_mut(index.checked_sub(self.first_loaded)?)
+    }
+
+    /// The rows that are loaded, by their index.
+    #[must_use]
+    pub fn loaded(&self) -> Range<usize> {
+        self.first_loaded..self.first_loaded.saturating_add(self.loaded.len())
+    }
+
+    pub fn loaded_rows_mut(&mut self) -> impl Iterator<Item = &mut Row> {
+        self.loaded.iter_mut()
+    }
+
+    /// Finds the first match of a query at a given position or after it, or the last one that
+    /// starts before it if not `forward`. The file is searched in the background: `None` means
+    /// that the search is still running, and asking again with the same arguments gets its
+    /// result once it's done. Asking for anything else cancels it.
+    #[must_use]
+    pub fn find(&self, query: &Query, from: &Position, forward: bool) -> Option<Found> {
+        let mut search = self.search.borrow_mut();
+        if let Some(running) = search.as_ref() {
+            if running.query == *query && running.from == *from && running.forward == forward {
+                // A search that panicked counts as having found nothing.
+                return running
+                    .result
+                    .lock()
+                    .map_or(Some(None), |result| result.clone());
+            }
+            running.cancel();
+        }
+        let started = Search {
+            query: query.clone(),
+            from: from.clone(),
+            forward,
+            is_canceled: Arc::new(AtomicBool::new(false)),
+            result: Arc::new(Mutex::new(None)),
+        };
+        let lines = self.lines.clone();
+        let (query, from) = (started.query.clone(), started.from.clone());
+        let is_canceled = Arc::clone(&started.is_canceled);
+        let result = Arc::clone(&started.result);
+        thread::spawn(move || {
+            let found = if forward {
+                lines.find_after(&query, &from, &is_canceled)
+            } else {
+                lines.rfind_before(&query, &from, &is_canceled)
+            };
+            if let Ok(mut result) = result.lock() {
+                *result = Some(found);
+            }
+        });
+        *search = Some(started);
+        None
+    }
+
+    /// Whether a search is still running in the background.
+    #[must_use]
+    pub fn is_searching(&self) -> bool {
+        self.search
+            .borrow()
+            .as_ref()
+            .is_some_and(|search| !search.is_done())
+    }
+
+    /// Stops the search running in the background, if any.
+    pub fn cancel_search(&self) {
+        if let Some(search) = self.search.borrow_mut().take() {
+            search.cancel();
+        }
+    }
+}
+
+impl Lines {
+    /// The lines from the line at `from` on, read from the disk. `None` if that line hasn't been
+    /// indexed yet.
+    #[allow(clippy::integer_division)]
+    fn lines_from(&self, from: usize) -> Option<impl Iterator<Item = String>> {
+        let checkpoint = self
+            .index
+            .lock()
+            .ok()?
+            .checkpoints
+            .get(from / LINES_PER_CHECKPOINT)
+            .copied()?;
+        let mut reader = BufReader::new(File::open(&self.filename).ok()?);
+        reader.seek(SeekFrom::Start(checkpoint)).ok()?;
+        let lines = reader.split(b'\n').map_while(Result::ok).map(|line| {
+            let line = String::from_utf8_lossy(&line);
+            line.strip_suffix('\r').unwrap_or(&line).to_owned()
+        });
+        Some(lines.skip(from % LINES_PER_CHECKPOINT))
+    }
+
+    /// Finds the first match of a query at a given position or after it, reading on through the
+    /// file until it's found or the search is canceled.
+    fn find_after(&self, query: &Query, after: &Position, is_canceled: &AtomicBool) -> Found {
+        let mut x = after.x;
+        for (line, y) in self.lines_from(after.y)?.zip(after.y..) {
+            if is_canceled.load(Ordering::Relaxed) {
+                return None;
+            }
+            if let Some(found) = Row::from(line.as_str()).find_after(query, x) {
+                return Some((Position { x: found.start, y }, found.len()));
+            }
+            // Only the start row is affected by the `after` position.
+            x = 0;
+        }
+        None
+    }
+
+    /// Finds the last match of a query that starts before a given position, reading back through
+    /// the file a checkpoint at a time until it's found or the search is canceled.
+    #[allow(clippy::integer_division)]
+    fn rfind_before(&self, query: &Query, before: &Position, is_canceled: &AtomicBool) -> Found {
+        let mut start = (before.y / LINES_PER_CHECKPOINT).saturating_mul(LINES_PER_CHECKPOINT);
+        let mut end = before.y.saturating_add(1);
+        loop {
+            if is_canceled.load(Ordering::Relaxed) {
+                return None;
+            }
+            let lines: Vec<String> = self
+                .lines_from(start)?
+                .take(end.saturating_sub(start))
+                .collect();
+            for (i, line) in lines.iter().enumerate().rev() {
+                let y = start.saturating_add(i);
+                let row = Row::from(line.as_str());
+                // Only the start row is affected by the `before` position.
+                let x = if y == before.y { before.x } else { row.len() };
+                if let Some(found) = row.rfind_before(query, x) {
+                    return Some((Position { x: found.start, y }, found.len()));
+                }
+            }
+            end = start;
+            start = start.checked_sub(LINES_PER_CHECKPOINT)?;
+        }
+    }
+}
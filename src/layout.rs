@@ -0,0 +1,217 @@
+use core::cmp;
+use core::mem;
+
+/// The fewest lines a window takes up, one for text and one for its status line.
+const MIN_HEIGHT: usize = 2;
+/// The fewest columns a window takes up.
+const MIN_WIDTH: usize = 1;
+
+/// A part of the screen, in columns and lines from the top-left corner.
+#[derive(Default, Copy, Clone)]
+pub struct Rect {
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+}
+
+/// How a split divides its part of the screen.
+#[derive(PartialEq, Copy, Clone)]
+pub enum Direction {
+    /// One window above the other, divided by the status line of the upper one.
+    Horizontal,
+    /// The windows side by side, divided by a separator column.
+    Vertical,
+}
+
+/// How the screen is divided into windows, which are referred to by their index.
+pub enum Layout {
+    Window(usize),
+    Split {
+        direction: Direction,
+        /// The lines or columns taken up by the first part.
+        first_size: usize,
+        first: Box<Layout>,
+        second: Box<Layout>,
+    },
+}
+
+impl Default for Layout {
+    fn default() -> Self {
+        Self::Window(0)
+    }
+}
+
+impl Layout {
+    /// The part of `area` taken up by each window, in the order they appear on the screen, along
+    /// with the separators between windows side by side.
+    #[must_use]
+    pub fn arrange(&self, area: Rect) -> (Vec<(usize, Rect)>, Vec<Rect>) {
+        let mut windows = Vec::new();
+        let mut separators = Vec::new();
+        self.arrange_into(area, &mut windows, &mut separators);
+        (windows, separators)
+    }
+
+    fn arrange_into(
+        &self,
+        area: Rect,
+        windows: &mut Vec<(usize, Rect)>,
+        separators: &mut Vec<Rect>,
+    ) {
+        match self {
+            Self::Window(index) => windows.push((*index, area)),
+            Self::Split {
+                direction,
+                first_size,
+                first,
+                second,
+            } => {
+                let (first_area, separator, second_area) =
+                    Self::divide(area, *direction, *first_size);
+                first.arrange_into(first_area, windows, separators);
+                separators.extend(separator);
+                second.arrange_into(second_area, windows, separators);
+            }
+        }
+    }
+
+    /// Divides `area` into the parts of a split, keeping each of them at least the minimum size.
+    fn divide(area: Rect, direction: Direction, first_size: usize) -> (Rect, Option<Rect>, Rect) {
+        match direction {
+            Direction::Horizontal => {
+                let height = Self::clamp(first_size, MIN_HEIGHT, area.height, 0);
+                let first = Rect { height, ..area };
+                let second = Rect {
+                    y: area.y.saturating_add(height),
+                    height: area.height.saturating_sub(height),
+                    ..area
+                };
+                (first, None, second)
+            }
+            Direction::Vertical => {
+                let width = Self::clamp(first_size, MIN_WIDTH, area.width, 1);
+                let first = Rect { width, ..area };
+                let separator = Rect {
+                    x: area.x.saturating_add(width),
+                    width: 1,
+                    ..area
+                };
+                let second = Rect {
+                    x: separator.x.saturating_add(1),
+                    width: area.width.saturating_sub(width).saturating_sub(1),
+                    ..area
+                };
+                (first, Some(separator), second)
+            }
+        }
+    }
+
+    /// Limits the size of the first part of a split to what leaves `min` for the second part,
+    /// besides the `separator`.
+    fn clamp(first_size: usize, min: usize, total: usize, separator: usize) -> usize {
+        let max = total.saturating_sub(min).saturating_sub(separator);
+        cmp::min(cmp::max(first_size, min), max)
+    }
+
+    #[must_use]
+    pub fn contains(&self, window: usize) -> bool {
+        match self {
+            Self::Window(index) => *index == window,
+            Self::Split { first, second, .. } => first.contains(window) || second.contains(window),
+        }
+    }
+
+    /// Splits `window`, with `new_window` below or to the right of it. Returns whether `window`
+    /// was found.
+    pub fn split(
+        &mut self,
+        window: usize,
+        new_window: usize,
+        direction: Direction,
+        first_size: usize,
+    ) -> bool {
+        match self {
+            Self::Window(index) => {
+                if *index != window {
+                    return false;
+                }
+                *self = Self::Split {
+                    direction,
+                    first_size,
+                    first: Box::new(Self::Window(window)),
+                    second: Box::new(Self::Window(new_window)),
+                };
+                true
+            }
+            Self::Split { first, second, .. } => {
+                first.split(window, new_window, direction, first_size)
+                    || second.split(window, new_window, direction, first_size)
+            }
+        }
+    }
+
+    /// Removes `window`, whose part of the screen goes to the other side of its split. The windows
+    /// after it move down by one index, as they do in a `Vec`.
+    pub fn remove(&mut self, window: usize) {
+        if let Self::Split { first, second, .. } = self {
+            if matches!(**first, Self::Window(index) if index == window) {
+                *self = *mem::take(second);
+            } else if matches!(**second, Self::Window(index) if index == window) {
+                *self = *mem::take(first);
+            } else {
+                first.remove(window);
+                second.remove(window);
+                return;
+            }
+        }
+        self.renumber(window);
+    }
+
+    fn renumber(&mut self, removed: usize) {
+        match self {
+            Self::Window(index) => {
+                if *index > removed {
+                    *index = index.saturating_sub(1);
+                }
+            }
+            Self::Split { first, second, .. } => {
+                first.renumber(removed);
+                second.renumber(removed);
+            }
+        }
+    }
+
+    /// Moves the divider of the innermost split around `window` by one line or column, so that
+    /// `window` grows or shrinks. Returns whether there was such a split.
+    pub fn resize(&mut self, window: usize, grow: bool, area: Rect) -> bool {
+        let Self::Split {
+            direction,
+            first_size,
+            first,
+            second,
+        } = self
+        else {
+            return false;
+        };
+        let (first_area, _, second_area) = Self::divide(area, *direction, *first_size);
+        if first.resize(window, grow, first_area) || second.resize(window, grow, second_area) {
+            return true;
+        }
+        let in_first = first.contains(window);
+        if !in_first && !second.contains(window) {
+            return false;
+        }
+        // The size is kept to what is shown, so that it doesn't grow past the screen unseen.
+        *first_size = match direction {
+            Direction::Horizontal => first_area.height,
+            Direction::Vertical => first_area.width,
+        };
+        *first_size = if in_first == grow {
+            first_size.saturating_add(1)
+        } else {
+            first_size.saturating_sub(1)
+        };
+        true
+    }
+}
@@ -0,0 +1,35 @@
+/// How a row break is written to the file.
+#[derive(Default, PartialEq, Copy, Clone)]
+pub enum LineEnding {
+    #[default]
+    Lf,
+    /// As written on Windows.
+    Crlf,
+}
+
+impl LineEnding {
+    #[must_use]
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            LineEnding::Lf => "\n",
+            LineEnding::Crlf => "\r\n",
+        }
+    }
+
+    #[must_use]
+    pub fn name(&self) -> &'static str {
+        match self {
+            LineEnding::Lf => "LF",
+            LineEnding::Crlf => "CRLF",
+        }
+    }
+
+    /// The other line ending, which the document is converted to.
+    #[must_use]
+    pub fn toggled(&self) -> Self {
+        match self {
+            LineEnding::Lf => LineEnding::Crlf,
+            LineEnding::Crlf => LineEnding::Lf,
+        }
+    }
+}
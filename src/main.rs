@@ -22,17 +22,31 @@
     clippy::panic,
     clippy::unseparated_literal_suffix
 )]
+extern crate alloc;
+
+mod clipboard;
+mod completion;
 mod document;
 mod editor;
+mod encoding;
 mod filetype;
 mod highlight;
+mod history;
+mod large_file;
+mod layout;
+mod line_ending;
 mod row;
+mod row_tree;
+mod search;
 mod terminal;
 pub use document::Document;
 pub use editor::Position;
 pub use filetype::FileType;
 pub use highlight::HighlightingOptions;
+pub use history::History;
+pub use line_ending::LineEnding;
 pub use row::Row;
+pub use row_tree::RowTree;
 pub use terminal::Terminal;
 
 use editor::Editor;
@@ -1,15 +1,22 @@
 use crate::highlight;
+use crate::search::Query;
 use crate::HighlightingOptions;
+use crate::LineEnding;
 use core::cmp;
+use core::ops::Range;
 
-use termion::color;
+use termion::{color, style};
 use unicode_segmentation::UnicodeSegmentation;
 
 #[derive(Default)]
 pub struct Row {
     string: String,
     highlight: Vec<highlight::Type>,
-    len: usize,
+    /// The byte index at which each grapheme starts, so that graphemes can be looked up by index
+    /// without segmenting the whole string again. Its length is the length of the row.
+    graphemes: Vec<usize>,
+    /// How the row break after the row is written, kept as it was read.
+    ending: LineEnding,
 }
 
 /// The information after the row that is highlighted; may affect the highlighting of the next row.
@@ -22,110 +29,300 @@ pub struct HighlightContext {
 
 impl From<&str> for Row {
     fn from(s: &str) -> Self {
-        let mut row = Self {
+        Self {
             string: String::from(s),
             highlight: Vec::new(),
-            len: 0,
-        };
-        row.update_len();
-        row
+            graphemes: s.grapheme_indices(true).map(|(index, _)| index).collect(),
+            ending: LineEnding::default(),
+        }
     }
 }
 
 impl Row {
+    /// Renders the screen columns from `start` up until `end`, with tabs expanded to the next
+    /// multiple of `tab_stop`, highlighting the graphemes in `selections`.
     #[must_use]
-    pub fn render(&self, start: usize, end: usize) -> String {
-        // Get the actual end of such row.
-        let end = cmp::min(end, self.string.len());
-        // In case that `start` is greater than `end`, we want to return an empty string.
-        let start = cmp::min(start, end);
+    pub fn render(
+        &self,
+        start: usize,
+        end: usize,
+        selections: &[Range<usize>],
+        tab_stop: usize,
+    ) -> String {
+        let first = self.index_at_column(start, tab_stop);
+        let mut column = self.column_of(first, tab_stop);
         let mut result = String::new();
         let mut curr_highlight = &highlight::Type::None;
         #[allow(clippy::arithmetic_side_effects)]
         for (index, grapheme) in self
-            .string
-            .as_str()
+            .slice(first, self.len())
             .graphemes(true)
             .enumerate()
-            .skip(start /* the ones to the left of the screen */)
-            .take(end - start /* the visible portion of the row */)
+            .map(|(index, grapheme)| (first + index, grapheme))
         {
-            // A tab is converted to a single space.
-            if let Some(c) = grapheme.chars().next() {
+            if column >= end {
+                break;
+            }
+            let highlight_type = if selections
+                .iter()
+                .any(|selection| selection.contains(&index))
+            {
+                &highlight::Type::Selection
+            } else {
                 // NOTE: In case some internal error occurs, we want to keep from crashing.
-                let highlight_type = self.highlight.get(index).unwrap_or(&highlight::Type::None);
-                // Insert a new color sequence only if the color has changed.
-                if highlight_type != curr_highlight {
-                    curr_highlight = highlight_type;
-                    let start_highlight = format!("{}", color::Fg(highlight_type.as_color()));
-                    result.push_str(&start_highlight);
+                self.highlight.get(index).unwrap_or(&highlight::Type::None)
+            };
+            // Insert a new color sequence only if the color has changed.
+            if highlight_type != curr_highlight {
+                if *highlight_type == highlight::Type::Selection {
+                    let start_selection = format!("{}", style::Invert);
+                    result.push_str(&start_selection);
+                } else if *curr_highlight == highlight::Type::Selection {
+                    let end_selection = format!("{}", style::NoInvert);
+                    result.push_str(&end_selection);
                 }
-                // NOTE: If converting to multiple spaces, special care would be needed to
-                // maintain the cursor position, as well as leaving it as it is.
-                result.push(if c == '\t' { ' ' } else { c });
+                curr_highlight = highlight_type;
+                let start_highlight = format!("{}", color::Fg(highlight_type.as_color()));
+                result.push_str(&start_highlight);
+            }
+            let width = Self::width(grapheme, column, tab_stop);
+            if grapheme == "\t" {
+                // A tab may be cut off at either edge of the screen.
+                let visible = cmp::min(column + width, end) - cmp::max(column, start);
+                result.push_str(&" ".repeat(visible));
+            } else {
+                result.push_str(grapheme);
             }
+            column += width;
         }
-        let end_highlight = format!("{}", color::Fg(color::Reset));
+        let end_highlight = format!("{}{}", color::Fg(color::Reset), style::NoInvert);
         result.push_str(&end_highlight);
         result
     }
 
+    /// The number of screen columns taken by a grapheme starting at `column`. A tab reaches up to
+    /// the next tab stop.
+    fn width(grapheme: &str, column: usize, tab_stop: usize) -> usize {
+        if grapheme == "\t" {
+            tab_stop.saturating_sub(column.checked_rem(tab_stop).unwrap_or(0))
+        } else {
+            1
+        }
+    }
+
+    /// The screen column at which the grapheme at `at` starts.
+    #[must_use]
+    pub fn column_of(&self, at: usize, tab_stop: usize) -> usize {
+        self.slice(0, at)
+            .graphemes(true)
+            .fold(0, |column, grapheme| {
+                column.saturating_add(Self::width(grapheme, column, tab_stop))
+            })
+    }
+
+    /// The index of the grapheme that covers the screen column `column`, or the length of the row if
+    /// the column is past its end.
+    #[must_use]
+    pub fn index_at_column(&self, column: usize, tab_stop: usize) -> usize {
+        let mut start = 0usize;
+        for (index, grapheme) in self.slice(0, self.len()).graphemes(true).enumerate() {
+            let end = start.saturating_add(Self::width(grapheme, start, tab_stop));
+            if end > column {
+                return index;
+            }
+            start = end;
+        }
+        self.len()
+    }
+
+    /// The indices of the graphemes that start each screen line when the row is wrapped to
+    /// `width` columns. Lines are broken after whitespace where possible, and within a word
+    /// otherwise. The first line always starts at 0. A row whose last line is full gets an empty
+    /// line after it, where the cursor at the end of the row goes.
+    #[must_use]
+    pub fn wrap_points(&self, width: usize, tab_stop: usize) -> Vec<usize> {
+        let mut points = vec![0];
+        let (mut line_start, mut line_start_column) = (0, 0usize);
+        // The last place to break after whitespace, along with its column.
+        let mut last_break = None;
+        let mut column = 0usize;
+        for (index, grapheme) in self.slice(0, self.len()).graphemes(true).enumerate() {
+            let grapheme_width = Self::width(grapheme, column, tab_stop);
+            while column.saturating_add(grapheme_width) > line_start_column.saturating_add(width)
+                && line_start < index
+            {
+                (line_start, line_start_column) = last_break
+                    .take()
+                    .filter(|(at, _)| *at > line_start)
+                    .unwrap_or((index, column));
+                points.push(line_start);
+            }
+            column = column.saturating_add(grapheme_width);
+            if grapheme.chars().all(char::is_whitespace) {
+                last_break = Some((index.saturating_add(1), column));
+            }
+        }
+        if width > 0 && column >= line_start_column.saturating_add(width) {
+            points.push(self.len());
+        }
+        points
+    }
+
     #[must_use]
     pub fn len(&self) -> usize {
-        self.len
+        self.graphemes.len()
     }
 
     #[must_use]
     pub fn is_empty(&self) -> bool {
-        self.len == 0
+        self.graphemes.is_empty()
     }
 
-    /// To avoid recomputing the length of the row every time we need it.
-    fn update_len(&mut self) {
-        self.len = self.string.as_str().graphemes(true).count();
+    /// The byte index of the grapheme at `at`, or the end of the string if `at` is past the last grapheme.
+    fn byte_index(&self, at: usize) -> usize {
+        self.graphemes.get(at).copied().unwrap_or(self.string.len())
     }
 
-    pub fn insert(&mut self, at: usize, c: char) {
-        if at >= self.len() {
-            self.string.push(c);
-        } else {
-            // Splits the string into two half, inserts the character after the
-            // first part, and then append the second part.
-            let mut result: String = self.string.as_str().graphemes(true).take(at).collect();
-            let reminder: String = self.string.as_str().graphemes(true).skip(at).collect();
-            result.push(c);
-            result.push_str(&reminder);
-            self.string = result;
+    /// The graphemes from `start` up until `end`.
+    #[must_use]
+    pub fn slice(&self, start: usize, end: usize) -> &str {
+        self.string
+            .get(self.byte_index(start)..self.byte_index(end))
+            .unwrap_or_default()
+    }
+
+    /// The word around the grapheme at `at`, made up of everything but separators.
+    #[must_use]
+    pub fn word_at(&self, at: usize) -> Option<Range<usize>> {
+        let is_word = |index: usize| {
+            self.grapheme(index)
+                .and_then(|grapheme| grapheme.chars().next())
+                .is_some_and(|c| !Self::is_separator(c))
+        };
+        if !is_word(at) {
+            return None;
+        }
+        let mut start = at;
+        while start > 0 && is_word(start.saturating_sub(1)) {
+            start = start.saturating_sub(1);
         }
-        self.update_len();
+        let mut end = at;
+        while is_word(end) {
+            end = end.saturating_add(1);
+        }
+        Some(start..end)
     }
 
-    #[allow(clippy::arithmetic_side_effects)]
-    pub fn delete(&mut self, at: usize) {
+    fn grapheme(&self, at: usize) -> Option<&str> {
         if at >= self.len() {
-            return;
+            return None;
+        }
+        Some(self.slice(at, at.saturating_add(1)))
+    }
+
+    /// Updates the grapheme indices after `removed` bytes at the grapheme `at`, starting at
+    /// `edit_byte`, have been replaced by `inserted` bytes. Only the graphemes around the edit are
+    /// segmented again, until the boundaries line up with the old ones. The boundaries behind the
+    /// edit are shifted, so an edit takes time linear in the length of its row, like moving the
+    /// bytes of the string does, while the rest of the document is left alone.
+    #[allow(clippy::arithmetic_side_effects)] // The shifted boundaries are behind the removed bytes.
+    fn reindex(&mut self, at: usize, edit_byte: usize, removed: usize, inserted: usize) {
+        // The edit may merge with the grapheme before it, e.g. when inserting a combining mark.
+        let first = cmp::min(at.saturating_sub(1), self.len());
+        let start_byte = self.graphemes.get(first).copied().unwrap_or(edit_byte);
+        let edit_end = edit_byte + inserted;
+        let old_edit_end = edit_byte + removed;
+        let shift = |boundary: usize| boundary - removed + inserted;
+        // The old boundaries behind the edit, which are reused once the segmentation agrees.
+        let mut tail = self
+            .graphemes
+            .partition_point(|&boundary| boundary < old_edit_end);
+        let mut segmented = Vec::new();
+        let mut is_synchronized = false;
+        for (index, _) in self
+            .string
+            .get(start_byte..)
+            .unwrap_or_default()
+            .grapheme_indices(true)
+        {
+            let boundary = start_byte + index;
+            while self
+                .graphemes
+                .get(tail)
+                .is_some_and(|&old| shift(old) < boundary)
+            {
+                tail += 1;
+            }
+            if boundary >= edit_end
+                && self
+                    .graphemes
+                    .get(tail)
+                    .is_some_and(|&old| shift(old) == boundary)
+            {
+                is_synchronized = true;
+                break;
+            }
+            segmented.push(boundary);
+        }
+        if !is_synchronized {
+            tail = self.graphemes.len();
+        }
+        if let Some(reused) = self.graphemes.get_mut(tail..) {
+            for boundary in reused {
+                *boundary = shift(*boundary);
+            }
         }
-        let mut result: String = self.string.as_str().graphemes(true).take(at).collect();
-        let remainder: String = self.string.as_str().graphemes(true).skip(at + 1).collect();
-        result.push_str(&remainder);
-        self.string = result;
-        self.update_len();
+        self.graphemes.splice(first..tail, segmented);
+    }
+
+    pub fn insert(&mut self, at: usize, c: char) {
+        self.insert_str(at, c.encode_utf8(&mut [0; 4]));
+    }
+
+    pub fn insert_str(&mut self, at: usize, s: &str) {
+        let at = cmp::min(at, self.len());
+        let byte_index = self.byte_index(at);
+        self.string.insert_str(byte_index, s);
+        self.reindex(at, byte_index, 0, s.len());
+    }
+
+    /// Deletes the grapheme at a given index and returns it.
+    pub fn delete(&mut self, at: usize) -> Option<String> {
+        let deleted = self.grapheme(at)?.to_owned();
+        let byte_index = self.byte_index(at);
+        self.string
+            .replace_range(byte_index..byte_index.saturating_add(deleted.len()), "");
+        self.reindex(at, byte_index, deleted.len(), 0);
+        Some(deleted)
     }
 
+    /// Appends the content of another row, whose row break becomes the one of this row.
     pub fn append(&mut self, new: &Self) {
-        self.string = format!("{}{}", self.string, new.string);
-        self.update_len();
+        self.ending = new.ending;
+        let at = self.len();
+        let byte_index = self.string.len();
+        self.string.push_str(&new.string);
+        self.reindex(at, byte_index, 0, new.string.len());
     }
 
     /// Truncates the current row up until a given index, and returns another row with
-    /// everything behind that index.
+    /// everything behind that index. The returned row takes over the row break of this row.
     #[must_use]
     pub fn split(&mut self, at: usize) -> Self {
-        let beginning: String = self.string.as_str().graphemes(true).take(at).collect();
-        let remainder: String = self.string.as_str().graphemes(true).skip(at).collect();
-        self.string = beginning;
-        self.update_len();
-        Self::from(&*remainder)
+        let remainder = self.string.split_off(self.byte_index(at));
+        self.graphemes.truncate(at);
+        let mut new_row = Self::from(&*remainder);
+        new_row.ending = self.ending;
+        new_row
+    }
+
+    #[must_use]
+    pub fn ending(&self) -> LineEnding {
+        self.ending
+    }
+
+    pub fn set_ending(&mut self, ending: LineEnding) {
+        self.ending = ending;
     }
 
     #[must_use]
@@ -133,68 +330,82 @@ impl Row {
         self.string.as_bytes()
     }
 
-    /// Finds the index of the first occurrence of a query string after a given index.
-    /// An empty query string will return `None`.
+    /// Finds the graphemes of the first match of a query starting at a given index or after it.
     #[must_use]
-    pub fn find_after(&self, query: &str, after: usize) -> Option<usize> {
-        if after >= self.len() || query.is_empty() {
+    pub fn find_after(&self, query: &Query, after: usize) -> Option<Range<usize>> {
+        if after > self.len() {
             return None;
         }
-        let substring: String = self.string.as_str().graphemes(true).skip(after).collect();
-        let match_byte_index = substring.find(query);
-        if let Some(match_byte_index) = match_byte_index {
-            for (grapheme_index, (byte_index, _)) in
-                substring.as_str().grapheme_indices(true).enumerate()
-            {
-                if byte_index == match_byte_index {
-                    #[allow(clippy::arithmetic_side_effects)]
-                    return Some(after + grapheme_index);
-                }
+        let mut start = self.byte_index(after);
+        loop {
+            let found = query.find_at(&self.string, start)?;
+            if let Some(range) = self.grapheme_range(&found) {
+                return Some(range);
             }
+            // A match that doesn't start at a grapheme boundary is not a match, so the search
+            // goes on from the next grapheme.
+            let next = self
+                .graphemes
+                .partition_point(|&index| index <= found.start);
+            start = self.byte_index(next);
         }
-        None
     }
 
-    /// Finds the index of the last occurrence of a query string before a given index. `before` is
-    /// excluded from the search. An empty query string will return `None`.
+    /// Finds the graphemes of the last match of a query that starts before a given index.
+    /// `before` is excluded from the search. The matches are stepped through like `find_after`
+    /// steps through them, so that overlapping ones are found going either way.
     #[must_use]
-    pub fn rfind_before(&self, query: &str, before: usize) -> Option<usize> {
-        if before == 0 || query.is_empty() {
-            return None;
-        }
+    pub fn rfind_before(&self, query: &Query, before: usize) -> Option<Range<usize>> {
         // NOTE: Since a before exceeding the length of the row doesn't affect the result,
         // we permit it.
-        let substring: String = self.string.as_str().graphemes(true).take(before).collect();
-        let match_byte_index = substring.rfind(query);
-        if let Some(match_byte_index) = match_byte_index {
-            for (grapheme_index, (byte_index, _)) in
-                substring.as_str().grapheme_indices(true).enumerate()
-            {
-                if byte_index == match_byte_index {
-                    return Some(grapheme_index);
-                }
+        let mut last = None;
+        let mut after = 0;
+        while let Some(found) = self.find_after(query, after) {
+            if found.start >= before {
+                break;
             }
+            after = found.start.saturating_add(1);
+            last = Some(found);
         }
-        None
+        last
+    }
+
+    /// The graphemes that a byte range of a match covers, or `None` if it doesn't start at a
+    /// grapheme boundary.
+    fn grapheme_range(&self, bytes: &Range<usize>) -> Option<Range<usize>> {
+        let start = if bytes.start == self.string.len() {
+            self.len()
+        } else {
+            self.graphemes.binary_search(&bytes.start).ok()?
+        };
+        // A match that ends within a grapheme covers all of it.
+        let end = self.graphemes.partition_point(|&index| index < bytes.end);
+        Some(start..end)
+    }
+
+    /// What replaces the match of a query at a given index.
+    #[must_use]
+    pub fn expand(&self, query: &Query, at: usize, replacement: &str) -> String {
+        query.expand(&self.string, self.byte_index(at), replacement)
     }
 
     /// Assuming that the character before `from` is not a backslash.
     #[must_use]
     #[allow(clippy::arithmetic_side_effects)] // Overflow checked by `checked_add`.
     fn forms_character_from(&self, from: usize) -> bool {
-        if let Some(c) = self.string.as_str().graphemes(true).nth(from) {
+        if let Some(c) = self.grapheme(from) {
             if c == "'" && from.checked_add(1).is_some() {
                 // There are two forms:
                 // - '.' (single character)
                 // - '\.' (escaped character)
                 // where '.' is any character except for a backslash.
-                if let Some(c) = self.string.as_str().graphemes(true).nth(from + 1) {
+                if let Some(c) = self.grapheme(from + 1) {
                     return (c != "\\"
                         && from.checked_add(2).is_some()
-                        && self.string.as_str().graphemes(true).nth(from + 2) == Some("'"))
+                        && self.grapheme(from + 2) == Some("'"))
                         || (c == "\\"
                             && from.checked_add(3).is_some()
-                            && self.string.as_str().graphemes(true).nth(from + 3) == Some("'"));
+                            && self.grapheme(from + 3) == Some("'"));
                 }
             }
         }
@@ -226,7 +437,7 @@ impl Row {
                         *keyword_len = keyword.len();
                         return keyword.len();
                     }
-                    if let Some(c) = self.string.as_str().graphemes(true).nth(next_index) {
+                    if let Some(c) = self.grapheme(next_index) {
                         if c.chars().next().map_or(false, Self::is_separator) {
                             *keyword_len = keyword.len();
                             return keyword.len();
@@ -264,7 +475,7 @@ impl Row {
                         *data_type_len = data_type.len();
                         return data_type.len();
                     }
-                    if let Some(c) = self.string.as_str().graphemes(true).nth(next_index) {
+                    if let Some(c) = self.grapheme(next_index) {
                         if c.chars().next().map_or(false, Self::is_separator) {
                             *data_type_len = data_type.len();
                             return data_type.len();
@@ -381,26 +592,22 @@ impl Row {
         }
     }
 
-    /// Highlights all occurrences of a query string in the row with other words untouched.
-    pub fn highlight_query(&mut self, query: &str) {
-        // Find the index of all occurrences of the query string.
-        let mut matches = Vec::new();
-        let mut start = 0;
-        while let Some(index) = self.find_after(query, start) {
-            matches.push(index);
-            if let Some(next_index) = index.checked_add(query.graphemes(true).count()) {
-                start = next_index;
-            } else {
-                break;
-            }
+    /// Highlights all matches of a query in the row with other words untouched.
+    pub fn highlight_query(&mut self, query: &Query) {
+        let matches: Vec<Range<usize>> = query
+            .find_iter(&self.string)
+            .filter_map(|found| self.grapheme_range(&found))
+            .collect();
+        for range in matches {
+            self.highlight_match(range);
         }
+    }
 
-        // Highlight the matches.
-        while let Some(index) = matches.pop() {
-            for i in index..index.saturating_add(query.graphemes(true).count()) {
-                if let Some(highlight) = self.highlight.get_mut(i) {
-                    *highlight = highlight::Type::Search;
-                }
+    /// Highlights the graphemes in `range` as a search match.
+    pub fn highlight_match(&mut self, range: Range<usize>) {
+        for i in range {
+            if let Some(highlight) = self.highlight.get_mut(i) {
+                *highlight = highlight::Type::Search;
             }
         }
     }
@@ -410,3 +617,98 @@ impl Row {
         (c.is_ascii_punctuation() && c != '_') || c.is_ascii_whitespace()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::search::SearchOptions;
+
+    /// The rows that an edit is checked on: ASCII, multi-byte graphemes and combining marks.
+    const ROWS: [&str; 4] = [
+        "",
+        "abc",
+        "a\u{f1}\u{20ac}\u{1f600}z",
+        "e\u{301}x\u{302}\u{303}y",
+    ];
+
+    /// Checks that the grapheme index of an edited row is the one segmented from scratch.
+    fn assert_indexed(row: &Row) {
+        assert_eq!(row.graphemes, Row::from(row.string.as_str()).graphemes);
+    }
+
+    #[test]
+    fn reindexes_inserts_at_the_start_middle_and_end() {
+        for text in ROWS {
+            for inserted in [
+                "q",
+                "\u{fc}",
+                "\u{301}",
+                "\u{1f600}\u{302}",
+                "ab",
+                "\u{301}c",
+            ] {
+                let len = Row::from(text).len();
+                #[allow(clippy::integer_division)]
+                for at in [0, len / 2, len] {
+                    let mut row = Row::from(text);
+                    row.insert_str(at, inserted);
+                    assert_indexed(&row);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn reindexes_deletes_at_the_start_middle_and_end() {
+        for text in ROWS {
+            let len = Row::from(text).len();
+            #[allow(clippy::integer_division)]
+            for at in [0, len / 2, len.saturating_sub(1)] {
+                let mut row = Row::from(text);
+                let deleted = row.delete(at);
+                assert_eq!(deleted.is_some(), len > 0);
+                assert_indexed(&row);
+            }
+        }
+    }
+
+    #[test]
+    fn merges_combining_marks_with_the_grapheme_before() {
+        let mut row = Row::from("ex");
+        row.insert(1, '\u{301}');
+        assert_eq!(row.len(), 2);
+        assert_eq!(row.slice(0, 1), "e\u{301}");
+        row.delete(0);
+        assert_eq!(row.string, "x");
+        assert_indexed(&row);
+    }
+
+    #[test]
+    fn reindexes_appends() {
+        let mut row = Row::from("a\u{20ac}");
+        row.append(&Row::from("\u{301}b"));
+        assert_eq!(row.len(), 3);
+        assert_indexed(&row);
+    }
+
+    #[test]
+    fn finds_overlapping_matches_going_either_way() {
+        let Ok(query) = Query::new("aa", SearchOptions::default()) else {
+            panic!("invalid pattern");
+        };
+        let row = Row::from("\u{e9}aaa");
+        assert_eq!(row.find_after(&query, 0), Some(1..3));
+        assert_eq!(row.find_after(&query, 2), Some(2..4));
+        assert_eq!(row.rfind_before(&query, 4), Some(2..4));
+        assert_eq!(row.rfind_before(&query, 2), Some(1..3));
+        assert_eq!(row.rfind_before(&query, 1), None);
+    }
+
+    #[test]
+    fn wraps_the_end_of_a_full_line_onto_the_next() {
+        assert_eq!(Row::from("abc").wrap_points(4, 8), [0]);
+        assert_eq!(Row::from("abcd").wrap_points(4, 8), [0, 4]);
+        assert_eq!(Row::from("ab cdef").wrap_points(4, 8), [0, 3, 7]);
+        assert_eq!(Row::from("ab cde").wrap_points(4, 8), [0, 3]);
+    }
+}
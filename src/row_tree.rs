@@ -0,0 +1,313 @@
+use crate::Row;
+use core::mem;
+
+/// The most rows a leaf holds before it's split in two.
+const MAX_LEAF_LEN: usize = 64;
+/// The most children an inner node holds before it's split in two.
+const MAX_CHILDREN: usize = 16;
+
+/// A node of the tree, which holds either rows or further nodes, all of them at the same depth.
+enum Node {
+    Leaf(Vec<Row>),
+    /// `len` is the number of rows below the node, so that a row is found by its index without
+    /// looking at the other branches.
+    Inner {
+        len: usize,
+        children: Vec<Node>,
+    },
+}
+
+impl Default for Node {
+    fn default() -> Self {
+        Self::Leaf(Vec::new())
+    }
+}
+
+impl Node {
+    fn len(&self) -> usize {
+        match self {
+            Self::Leaf(rows) => rows.len(),
+            Self::Inner { len, .. } => *len,
+        }
+    }
+
+    /// The child of an inner node that holds the row at `index`, along with the index of the row
+    /// within it. With `at_end`, an index right after the last row of a child is found in that
+    /// child, as where a row is inserted.
+    fn child_at(children: &[Self], mut index: usize, at_end: bool) -> Option<(usize, usize)> {
+        for (child_index, child) in children.iter().enumerate() {
+            if index < child.len() || (at_end && index == child.len()) {
+                return Some((child_index, index));
+            }
+            index = index.saturating_sub(child.len());
+        }
+        None
+    }
+
+    fn get(&self, index: usize) -> Option<&Row> {
+        match self {
+            Self::Leaf(rows) => rows.get(index),
+            Self::Inner { children, .. } => {
+                let (child, index) = Self::child_at(children, index, false)?;
+                children.get(child)?.get(index)
+            }
+        }
+    }
+
+    fn get_mut(&mut self, index: usize) -> Option<&mut Row> {
+        match self {
+            Self::Leaf(rows) => rows.get_mut(index),
+            Self::Inner { children, .. } => {
+                let (child, index) = Self::child_at(children, index, false)?;
+                children.get_mut(child)?.get_mut(index)
+            }
+        }
+    }
+
+    /// Inserts a row at `index`, which is at most the length of the node. Returns the second
+    /// half of the node if it had to be split, which goes right after it.
+    fn insert(&mut self, index: usize, row: Row) -> Option<Self> {
+        match self {
+            Self::Leaf(rows) => {
+                rows.insert(index, row);
+                if rows.len() <= MAX_LEAF_LEN {
+                    return None;
+                }
+                #[allow(clippy::integer_division)]
+                let second = rows.split_off(rows.len() / 2);
+                Some(Self::Leaf(second))
+            }
+            Self::Inner { len, children } => {
+                let (child, index) = Self::child_at(children, index, true)?;
+                *len = len.saturating_add(1);
+                let split = children.get_mut(child)?.insert(index, row)?;
+                children.insert(child.saturating_add(1), split);
+                if children.len() <= MAX_CHILDREN {
+                    return None;
+                }
+                #[allow(clippy::integer_division)]
+                let second = children.split_off(children.len() / 2);
+                let second_len = second.iter().map(Self::len).sum();
+                *len = len.saturating_sub(second_len);
+                Some(Self::Inner {
+                    len: second_len,
+                    children: second,
+                })
+            }
+        }
+    }
+
+    fn remove(&mut self, index: usize) -> Option<Row> {
+        match self {
+            Self::Leaf(rows) => (index < rows.len()).then(|| rows.remove(index)),
+            Self::Inner { len, children } => {
+                let (child, index) = Self::child_at(children, index, false)?;
+                let row = children.get_mut(child)?.remove(index)?;
+                *len = len.saturating_sub(1);
+                Self::merge_underfull(children, child);
+                Some(row)
+            }
+        }
+    }
+
+    /// Merges a child that has shrunk with a neighbor if they fit into a single node, and drops
+    /// it once it's empty, so that the tree doesn't fill up with small nodes.
+    fn merge_underfull(children: &mut Vec<Self>, child: usize) {
+        if children.get(child).is_some_and(|node| node.len() == 0) {
+            children.remove(child);
+            return;
+        }
+        let first = child.saturating_sub(1);
+        let second = if child == 0 { 1 } else { child };
+        let fits = match (children.get(first), children.get(second)) {
+            (Some(Self::Leaf(a)), Some(Self::Leaf(b))) => {
+                a.len().saturating_add(b.len()) <= MAX_LEAF_LEN
+            }
+            (Some(Self::Inner { children: a, .. }), Some(Self::Inner { children: b, .. })) => {
+                a.len().saturating_add(b.len()) <= MAX_CHILDREN
+            }
+            _ => false,
+        };
+        if !fits {
+            return;
+        }
+        let removed = children.remove(second);
+        match (children.get_mut(first), removed) {
+            (Some(Self::Leaf(rows)), Self::Leaf(more)) => rows.extend(more),
+            (
+                Some(Self::Inner { len, children }),
+                Self::Inner {
+                    len: more_len,
+                    children: more,
+                },
+            ) => {
+                *len = len.saturating_add(more_len);
+                children.extend(more);
+            }
+            _ => (),
+        }
+    }
+
+    fn iter(&self) -> Box<dyn DoubleEndedIterator<Item = &Row> + '_> {
+        match self {
+            Self::Leaf(rows) => Box::new(rows.iter()),
+            Self::Inner { children, .. } => Box::new(children.iter().flat_map(Self::iter)),
+        }
+    }
+
+    fn iter_mut(&mut self) -> Box<dyn Iterator<Item = &mut Row> + '_> {
+        match self {
+            Self::Leaf(rows) => Box::new(rows.iter_mut()),
+            Self::Inner { children, .. } => Box::new(children.iter_mut().flat_map(Self::iter_mut)),
+        }
+    }
+}
+
+/// The rows of a document, stored as a balanced tree whose nodes count the rows below them, so
+/// that looking up, inserting or removing a row takes time logarithmic in the length of the
+/// document. The text of each row is still a single string, so an edit within a row takes time
+/// linear in the length of that row.
+#[derive(Default)]
+pub struct RowTree {
+    root: Node,
+}
+
+impl FromIterator<Row> for RowTree {
+    fn from_iter<I: IntoIterator<Item = Row>>(iter: I) -> Self {
+        let mut tree = Self::default();
+        for row in iter {
+            tree.push(row);
+        }
+        tree
+    }
+}
+
+impl RowTree {
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.root.len()
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    #[must_use]
+    pub fn get(&self, index: usize) -> Option<&Row> {
+        self.root.get(index)
+    }
+
+    pub fn get_mut(&mut self, index: usize) -> Option<&mut Row> {
+        self.root.get_mut(index)
+    }
+
+    /// Inserts a row at `index`, shifting all rows after it down.
+    /// Nothing happens if `index` is past the end of the tree.
+    pub fn insert(&mut self, index: usize, row: Row) {
+        if index > self.len() {
+            return;
+        }
+        if let Some(second) = self.root.insert(index, row) {
+            // The root was split, so the tree grows by a level.
+            let first = mem::take(&mut self.root);
+            self.root = Node::Inner {
+                len: first.len().saturating_add(second.len()),
+                children: vec![first, second],
+            };
+        }
+    }
+
+    pub fn push(&mut self, row: Row) {
+        self.insert(self.len(), row);
+    }
+
+    /// Removes the row at `index`, shifting all rows after it up.
+    pub fn remove(&mut self, index: usize) -> Option<Row> {
+        let row = self.root.remove(index)?;
+        // A root with a single child is replaced by it, so the tree shrinks by a level.
+        if let Node::Inner { children, .. } = &mut self.root {
+            if children.len() <= 1 {
+                self.root = children.pop().unwrap_or_default();
+            }
+        }
+        Some(row)
+    }
+
+    pub fn pop(&mut self) -> Option<Row> {
+        self.remove(self.len().checked_sub(1)?)
+    }
+
+    #[must_use]
+    pub fn iter(&self) -> impl DoubleEndedIterator<Item = &Row> {
+        self.root.iter()
+    }
+
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = &mut Row> {
+        self.root.iter_mut()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn contents(tree: &RowTree) -> Vec<String> {
+        tree.iter()
+            .map(|row| row.slice(0, row.len()).to_owned())
+            .collect()
+    }
+
+    #[test]
+    fn keeps_the_order_of_rows_across_splits_and_merges() {
+        let mut tree = RowTree::default();
+        let mut expected: Vec<String> = Vec::new();
+        // Inserting in the middle splits leaves and inner nodes alike.
+        for i in 0..5000usize {
+            let at = i.wrapping_mul(7919) % (expected.len() + 1);
+            tree.insert(at, Row::from(i.to_string().as_str()));
+            expected.insert(at, i.to_string());
+        }
+        assert_eq!(tree.len(), expected.len());
+        assert_eq!(contents(&tree), expected);
+        for i in 0..4990usize {
+            let at = i.wrapping_mul(104_729) % expected.len();
+            let removed = tree
+                .remove(at)
+                .map(|row| row.slice(0, row.len()).to_owned());
+            assert_eq!(removed, Some(expected.remove(at)));
+        }
+        assert_eq!(contents(&tree), expected);
+        for (index, text) in expected.iter().enumerate() {
+            assert_eq!(
+                tree.get(index).map(|row| row.slice(0, row.len())),
+                Some(text.as_str())
+            );
+        }
+        assert!(tree.get(expected.len()).is_none());
+    }
+
+    #[test]
+    fn iterates_backwards() {
+        let tree: RowTree = (0..200)
+            .map(|i: usize| Row::from(i.to_string().as_str()))
+            .collect();
+        let last: Vec<String> = tree
+            .iter()
+            .rev()
+            .take(2)
+            .map(|row| row.slice(0, row.len()).to_owned())
+            .collect();
+        assert_eq!(last, ["199", "198"]);
+    }
+
+    #[test]
+    fn ignores_inserts_past_the_end() {
+        let mut tree = RowTree::default();
+        tree.insert(1, Row::from("a"));
+        assert!(tree.is_empty());
+        tree.push(Row::from("a"));
+        assert_eq!(tree.pop().map(|row| row.len()), Some(1));
+        assert!(tree.pop().is_none());
+    }
+}
@@ -0,0 +1,284 @@
+use core::fmt;
+use core::iter;
+use core::ops::Range;
+use regex::{Captures, Regex, RegexBuilder};
+use unicode_segmentation::UnicodeSegmentation;
+
+/// Whether letters have to be in the same case as in the query to match.
+#[derive(Default, PartialEq, Copy, Clone)]
+pub enum CaseMode {
+    #[default]
+    Sensitive,
+    Insensitive,
+    /// Insensitive unless the query has an uppercase letter.
+    Smart,
+}
+
+impl CaseMode {
+    /// The next mode, which the search prompt toggles to.
+    #[must_use]
+    pub fn next(self) -> Self {
+        match self {
+            CaseMode::Sensitive => CaseMode::Insensitive,
+            CaseMode::Insensitive => CaseMode::Smart,
+            CaseMode::Smart => CaseMode::Sensitive,
+        }
+    }
+}
+
+/// How the text of a query is matched, as toggled in the search prompt.
+#[derive(Default, PartialEq, Copy, Clone)]
+pub struct SearchOptions {
+    /// Whether the query is a regular expression rather than a literal string.
+    pub regex: bool,
+    pub case: CaseMode,
+    /// Whether a match has to start and end at word boundaries, as Unicode segments words.
+    pub whole_word: bool,
+}
+
+impl fmt::Display for SearchOptions {
+    /// The options that are on, as shown in the prompt.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let case = match self.case {
+            CaseMode::Sensitive => None,
+            CaseMode::Insensitive => Some("ignore case"),
+            CaseMode::Smart => Some("smart case"),
+        };
+        let on: Vec<&str> = [
+            self.regex.then_some("regex"),
+            case,
+            self.whole_word.then_some("whole word"),
+        ]
+        .into_iter()
+        .flatten()
+        .collect();
+        if on.is_empty() {
+            return Ok(());
+        }
+        write!(f, " [{}]", on.join(", "))
+    }
+}
+
+/// A query compiled to be matched against the rows. A literal query is compiled as the regular
+/// expression matching exactly its text.
+#[derive(Clone)]
+pub struct Query {
+    regex: Regex,
+    /// Whether replacements refer to capture groups, which they only do for a regular expression.
+    expands: bool,
+    case_insensitive: bool,
+    whole_word: bool,
+}
+
+#[allow(clippy::missing_trait_methods)] // `ne` is the negation of `eq` either way.
+impl PartialEq for Query {
+    /// Whether the queries match the same text.
+    fn eq(&self, other: &Self) -> bool {
+        self.regex.as_str() == other.regex.as_str()
+            && self.expands == other.expands
+            && self.case_insensitive == other.case_insensitive
+            && self.whole_word == other.whole_word
+    }
+}
+
+impl Query {
+    /// # Errors
+    /// Returns an error if the text is not a valid regular expression.
+    pub fn new(text: &str, options: SearchOptions) -> Result<Self, regex::Error> {
+        let pattern = if options.regex {
+            text.to_owned()
+        } else {
+            regex::escape(text)
+        };
+        let case_insensitive = match options.case {
+            CaseMode::Sensitive => false,
+            CaseMode::Insensitive => true,
+            CaseMode::Smart => !has_uppercase(text, options.regex),
+        };
+        Ok(Self {
+            regex: RegexBuilder::new(&pattern)
+                .case_insensitive(case_insensitive)
+                .build()?,
+            expands: options.regex,
+            case_insensitive,
+            whole_word: options.whole_word,
+        })
+    }
+
+    /// The byte range of the first match in `haystack` that starts at `start` or later. Anchors
+    /// and word boundaries still look at what comes before `start`.
+    #[must_use]
+    pub fn find_at(&self, haystack: &str, start: usize) -> Option<Range<usize>> {
+        let boundaries = self.word_boundaries(haystack);
+        self.find_from(haystack, boundaries.as_deref(), start)
+    }
+
+    /// Like `find_at`, with the word boundaries of `haystack` already found.
+    fn find_from(
+        &self,
+        haystack: &str,
+        boundaries: Option<&[usize]>,
+        mut start: usize,
+    ) -> Option<Range<usize>> {
+        loop {
+            if start > haystack.len() {
+                return None;
+            }
+            let found = self.regex.find_at(haystack, start)?.range();
+            if Self::is_whole_word(boundaries, &found) {
+                return Some(found);
+            }
+            // The search goes on from the next character.
+            let next = haystack.get(found.start..)?.chars().next()?;
+            start = found.start.saturating_add(next.len_utf8());
+        }
+    }
+
+    /// The byte ranges of all matches in `haystack` that don't overlap, from the start on. Each
+    /// one is found like `find_at` finds it, from the end of the one before.
+    pub fn find_iter<'text>(
+        &'text self,
+        haystack: &'text str,
+    ) -> impl Iterator<Item = Range<usize>> + 'text {
+        let boundaries = self.word_boundaries(haystack);
+        let mut start = 0;
+        iter::from_fn(move || {
+            let found = self.find_from(haystack, boundaries.as_deref(), start)?;
+            start = if found.is_empty() {
+                // An empty match is stepped over, so that it isn't found again.
+                let next = haystack
+                    .get(found.end..)
+                    .and_then(|rest| rest.chars().next());
+                found.end.saturating_add(next.map_or(1, char::len_utf8))
+            } else {
+                found.end
+            };
+            Some(found)
+        })
+    }
+
+    /// The byte indexes of the word boundaries in `haystack`, if matches have to be whole words.
+    fn word_boundaries(&self, haystack: &str) -> Option<Vec<usize>> {
+        self.whole_word.then(|| {
+            haystack
+                .split_word_bound_indices()
+                .map(|(index, _)| index)
+                .chain(iter::once(haystack.len()))
+                .collect()
+        })
+    }
+
+    /// Whether a match starts and ends at word boundaries, or doesn't have to.
+    fn is_whole_word(boundaries: Option<&[usize]>, found: &Range<usize>) -> bool {
+        boundaries.is_none_or(|boundaries| {
+            boundaries.binary_search(&found.start).is_ok()
+                && boundaries.binary_search(&found.end).is_ok()
+        })
+    }
+
+    /// What replaces the match starting at `start` in `haystack`. For a regular expression, `$1`,
+    /// `${name}` and the like in `replacement` stand for what the capture groups matched, and `$$`
+    /// for a dollar sign.
+    #[must_use]
+    pub fn expand(&self, haystack: &str, start: usize, replacement: &str) -> String {
+        if !self.expands {
+            return replacement.to_owned();
+        }
+        let mut expanded = String::new();
+        if let Some(captures) = self.regex.captures_at(haystack, start) {
+            Captures::expand(&captures, replacement, &mut expanded);
+        }
+        expanded
+    }
+}
+
+/// Whether the text of a query has an uppercase letter. Escapes of a regular expression, like
+/// `\S`, `\p{Lu}` or `\x4A`, don't count, including their arguments.
+fn has_uppercase(text: &str, regex: bool) -> bool {
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if !(regex && c == '\\') {
+            if c.is_uppercase() {
+                return true;
+            }
+            continue;
+        }
+        // The number of characters of the argument, unless it's in braces.
+        let argument_len = match chars.next() {
+            Some('p' | 'P') => 1,
+            Some('x') => 2,
+            Some('u') => 4,
+            Some('U') => 8,
+            _ => 0,
+        };
+        if argument_len > 0 && chars.as_str().starts_with('{') {
+            chars.by_ref().find(|&c| c == '}');
+        } else {
+            chars.by_ref().take(argument_len).for_each(drop);
+        }
+    }
+    false
+}
+
+/// The first line of the error of an invalid regular expression, which is short enough to be
+/// shown in the message bar.
+#[must_use]
+pub fn describe_error(error: &regex::Error) -> String {
+    match error {
+        regex::Error::Syntax(description) => description
+            .lines()
+            .map(str::trim)
+            .find(|line| line.starts_with("error:"))
+            .unwrap_or("invalid pattern")
+            .to_owned(),
+        _ => error.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn query(text: &str, options: SearchOptions) -> Query {
+        let Ok(query) = Query::new(text, options) else {
+            panic!("invalid pattern: {text}");
+        };
+        query
+    }
+
+    #[test]
+    fn finds_whole_words_that_overlap_rejected_matches() {
+        let options = SearchOptions {
+            regex: true,
+            whole_word: true,
+            ..SearchOptions::default()
+        };
+        let query = query("a b|b c", options);
+        // "a b" doesn't start at a word boundary, but the "b c" it overlaps does.
+        assert_eq!(query.find_at("xa b c", 0), Some(3..6));
+        let found: Vec<_> = query.find_iter("xa b c").collect();
+        assert_eq!(found.len(), 1);
+        assert_eq!(found.first(), Some(&(3..6)));
+    }
+
+    #[test]
+    fn finds_empty_matches_once() {
+        let options = SearchOptions {
+            regex: true,
+            ..SearchOptions::default()
+        };
+        let found: Vec<_> = query("x*", options).find_iter("a\u{e9}").collect();
+        assert_eq!(found, [0..0, 1..1, 3..3]);
+    }
+
+    #[test]
+    fn ignores_uppercase_letters_in_escapes() {
+        assert!(has_uppercase("Foo", false));
+        assert!(has_uppercase("\\S", false));
+        assert!(!has_uppercase("\\S\\W", true));
+        assert!(!has_uppercase("\\p{Lu}\\P{Greek}x", true));
+        assert!(!has_uppercase("\\pL\\x4A\\u004A\\x{4A}", true));
+        assert!(has_uppercase("\\p{Lu}X", true));
+        assert!(has_uppercase("\\pLX", true));
+    }
+}
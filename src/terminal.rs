@@ -1,10 +1,24 @@
 use crate::Position;
+use core::str;
+use core::time::Duration;
 use std::io::{self, stdout, Error, Write};
-use termion::event::Key;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::thread;
+use termion::event::{Event, Key};
 use termion::input::TermRead;
 use termion::raw::{IntoRawMode, RawTerminal};
 use termion::{clear, color, cursor};
 
+/// A modifier held down along with a key. Termion only reports unmodified keys, so the modified
+/// ones are recognized from their escape sequences.
+#[derive(PartialEq, Copy, Clone)]
+pub enum Modifier {
+    None,
+    Shift,
+    Alt,
+    ShiftAlt,
+}
+
 pub struct Size {
     pub width: u16,
     pub height: u16,
@@ -13,6 +27,8 @@ pub struct Size {
 pub struct Terminal {
     size: Size,
     _raw_stdout: RawTerminal<io::Stdout>,
+    /// The keys read by a thread of their own, so that waiting for one can time out.
+    keys: Receiver<Result<(Key, Modifier), Error>>,
 }
 
 impl Terminal {
@@ -29,9 +45,32 @@ impl Terminal {
             // For information on what are terminal modes, see
             // https://www.gnu.org/software/mit-scheme/documentation/stable/mit-scheme-ref/Terminal-Mode.html.
             _raw_stdout: stdout().into_raw_mode()?,
+            keys: Self::spawn_key_reader(),
         })
     }
 
+    fn spawn_key_reader() -> Receiver<Result<(Key, Modifier), Error>> {
+        let (sender, keys) = mpsc::channel();
+        thread::spawn(move || {
+            for event in io::stdin().events() {
+                let key = match event {
+                    Ok(Event::Key(key)) => Ok((key, Modifier::None)),
+                    Ok(Event::Unsupported(sequence)) => match Self::parse_modified_key(&sequence) {
+                        Some(key) => Ok(key),
+                        None => continue,
+                    },
+                    Ok(Event::Mouse(_)) => continue,
+                    Err(e) => Err(e),
+                };
+                // The editor has quit.
+                if sender.send(key).is_err() {
+                    break;
+                }
+            }
+        });
+        keys
+    }
+
     pub fn clear_screen() {
         print!("{}", clear::All);
     }
@@ -79,14 +118,81 @@ impl Terminal {
         io::stdout().flush()
     }
 
+    /// Copies to the clipboard of the terminal itself with an OSC 52 escape sequence, which also
+    /// works over SSH. Whether the terminal supports it can't be told.
     /// # Errors
-    /// Returns an error if the key can't be read from the terminal.
-    pub fn read_key() -> Result<Key, Error> {
-        loop {
-            if let Some(key) = io::stdin().lock().keys().next() {
-                return key;
+    /// Returns an error if the terminal is not flushed successfully.
+    #[allow(clippy::arithmetic_side_effects)] // The shifts stay within 24 bits.
+    pub fn set_clipboard(contents: &str) -> Result<(), Error> {
+        const ALPHABET: &[u8; 64] =
+            b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+        // The contents are base64-encoded, three bytes into four characters.
+        let mut encoded = String::new();
+        for chunk in contents.as_bytes().chunks(3) {
+            let [a, b, c] = [0usize, 1, 2].map(|i| u32::from(chunk.get(i).copied().unwrap_or(0)));
+            let bits = (a << 16u32) | (b << 8u32) | c;
+            for i in 0..4 {
+                let sextet = (bits >> (18 - 6 * i)) & 0x3f;
+                match ALPHABET.get(sextet as usize) {
+                    Some(&symbol) if i <= chunk.len() => encoded.push(char::from(symbol)),
+                    _ => encoded.push('='),
+                }
             }
         }
+        print!("\x1b]52;c;{encoded}\x07");
+        Self::flush()
+    }
+
+    /// # Errors
+    /// Returns an error if the key can't be read from the terminal.
+    pub fn read_key(&self) -> Result<(Key, Modifier), Error> {
+        self.keys
+            .recv()
+            .map_err(|_disconnected| Self::input_closed())?
+    }
+
+    /// Waits for a key for at most `timeout`. Returns `None` if no key was pressed in time.
+    /// # Errors
+    /// Returns an error if the key can't be read from the terminal.
+    pub fn read_key_timeout(&self, timeout: Duration) -> Result<Option<(Key, Modifier)>, Error> {
+        match self.keys.recv_timeout(timeout) {
+            Ok(key) => key.map(Some),
+            Err(RecvTimeoutError::Timeout) => Ok(None),
+            Err(RecvTimeoutError::Disconnected) => Err(Self::input_closed()),
+        }
+    }
+
+    fn input_closed() -> Error {
+        Error::new(
+            io::ErrorKind::UnexpectedEof,
+            "The input of the terminal was closed",
+        )
+    }
+
+    /// Parses the xterm sequences of modified keys, which are `ESC [ 1 ; <modifier> <key>` for
+    /// arrows, Home and End, and `ESC [ <key> ; <modifier> ~` for the others.
+    fn parse_modified_key(sequence: &[u8]) -> Option<(Key, Modifier)> {
+        let sequence = str::from_utf8(sequence).ok()?.strip_prefix("\x1b[")?;
+        let (parameters, last) = sequence.split_at(sequence.len().checked_sub(1)?);
+        let (code, modifier) = parameters.split_once(';')?;
+        let modifier = match modifier {
+            "2" => Modifier::Shift,
+            "3" => Modifier::Alt,
+            "4" => Modifier::ShiftAlt,
+            _ => return None,
+        };
+        let key = match (code, last) {
+            ("1", "A") => Key::Up,
+            ("1", "B") => Key::Down,
+            ("1", "C") => Key::Right,
+            ("1", "D") => Key::Left,
+            ("1", "H") => Key::Home,
+            ("1", "F") => Key::End,
+            ("5", "~") => Key::PageUp,
+            ("6", "~") => Key::PageDown,
+            _ => return None,
+        };
+        Some((key, modifier))
     }
 
     #[must_use]